@@ -0,0 +1,62 @@
+use super::ttl_cache::TtlCache;
+use crate::post_grab_api::probe_content_type;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use url::Url;
+
+fn default_cache_size() -> usize {
+    1024
+}
+
+fn default_cache_ttl_secs() -> u64 {
+    60 * 60
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ContentTypeProbeSettings {
+    #[serde(default = "default_cache_size")]
+    pub cache_size: usize,
+
+    #[serde(default = "default_cache_ttl_secs")]
+    pub cache_ttl_secs: u64,
+}
+
+/// Determines the content type of urls no module claimed by their path,
+/// remembering the results so that repeatedly posted links are only probed once.
+pub struct ContentTypeProbe {
+    cache: TtlCache<Url, Option<String>>,
+}
+
+impl ContentTypeProbe {
+    pub fn from_settings(settings: ContentTypeProbeSettings) -> Self {
+        Self {
+            cache: TtlCache::new(Duration::from_secs(settings.cache_ttl_secs), settings.cache_size),
+        }
+    }
+
+    /// The content type `url` is known to have without asking its server, if any.
+    pub fn cached(&self, url: &Url) -> Option<Option<String>> {
+        if !matches!(url.scheme(), "http" | "https") {
+            return Some(None);
+        }
+
+        self.cache.get(url)
+    }
+
+    pub async fn content_type(&self, url: &Url) -> Option<String> {
+        if let Some(content_type) = self.cached(url) {
+            return content_type;
+        }
+
+        let content_type = match probe_content_type(url.clone()).await {
+            Ok(content_type) => content_type,
+            Err(e) => {
+                tracing::info!("unable to probe content type of {}: {}", url, e);
+                None
+            },
+        };
+
+        self.cache.insert(url.clone(), content_type.clone());
+        content_type
+    }
+}
@@ -0,0 +1,425 @@
+use super::guild_settings::ChannelRating;
+use serenity::{
+    async_trait,
+    builder::{
+        Builder, CreateInteractionResponse, CreateInteractionResponseFollowup, CreateMessage, CreateThread,
+        CreateWebhook, EditInteractionResponse, EditMessage, ExecuteWebhook,
+    },
+    client::Context,
+    model::{
+        channel::{Channel, GuildChannel, Message, ReactionType},
+        id::{ChannelId, InteractionId, MessageId},
+        webhook::Webhook,
+        Permissions,
+    },
+};
+
+/// Name of the webhooks used to re-post messages on behalf of their authors
+const WEBHOOK_NAME: &str = "embedbot";
+
+/// Everything the bot asks of discord while handling events.
+#[async_trait]
+pub trait DiscordIo: Send + Sync {
+    async fn message(&self, channel: ChannelId, message: MessageId) -> serenity::Result<Message>;
+
+    async fn send_message(&self, channel: ChannelId, message: CreateMessage) -> serenity::Result<Message>;
+
+    async fn edit_message(&self, channel: ChannelId, message: MessageId, edit: EditMessage) -> serenity::Result<()>;
+
+    async fn delete_message(&self, channel: ChannelId, message: MessageId) -> serenity::Result<()>;
+
+    async fn react(&self, channel: ChannelId, message: MessageId, reaction: ReactionType) -> serenity::Result<()>;
+
+    /// Removes a reaction the bot added itself.
+    async fn unreact(&self, channel: ChannelId, message: MessageId, reaction: ReactionType) -> serenity::Result<()>;
+
+    /// Responds to the interaction `id`, which came with `token`.
+    async fn respond(
+        &self,
+        id: InteractionId,
+        token: &str,
+        response: CreateInteractionResponse,
+    ) -> serenity::Result<()>;
+
+    /// Replaces the response to the interaction that came with `token`, usually a deferred one.
+    async fn edit_response(&self, token: &str, edit: EditInteractionResponse) -> serenity::Result<Message>;
+
+    async fn delete_response(&self, token: &str) -> serenity::Result<()>;
+
+    /// Sends another message in response to the interaction that came with `token`.
+    async fn followup(&self, token: &str, followup: CreateInteractionResponseFollowup) -> serenity::Result<()>;
+
+    async fn start_thread(
+        &self,
+        channel: ChannelId,
+        message: MessageId,
+        thread: CreateThread<'static>,
+    ) -> serenity::Result<()>;
+
+    /// Posts `message` in `channel` through the webhook of the bot, so that it can appear under any name.
+    async fn repost(&self, channel: ChannelId, message: ExecuteWebhook) -> anyhow::Result<()>;
+
+    async fn channel_rating(&self, channel: ChannelId) -> ChannelRating;
+
+    /// The guild permissions of the author of `msg`, which message events do not include.
+    async fn author_permissions(&self, msg: &Message) -> serenity::Result<Permissions>;
+}
+
+/// Talks to discord through serenity.
+pub struct SerenityIo<'a> {
+    ctx: &'a Context,
+}
+
+impl<'a> SerenityIo<'a> {
+    pub fn new(ctx: &'a Context) -> Self {
+        Self { ctx }
+    }
+
+    /// Finds the webhook of the bot in `channel`, creating it if there is none yet.
+    async fn webhook(&self, channel: ChannelId) -> anyhow::Result<Webhook> {
+        let app = self.ctx.http.application_id();
+
+        let existing = channel
+            .webhooks(self.ctx)
+            .await?
+            .into_iter()
+            .find(|w| w.name.as_deref() == Some(WEBHOOK_NAME) && app.is_some() && w.application_id == app);
+
+        match existing {
+            Some(webhook) => Ok(webhook),
+            None => Ok(channel
+                .create_webhook(self.ctx, CreateWebhook::new(WEBHOOK_NAME))
+                .await?),
+        }
+    }
+}
+
+#[async_trait]
+impl DiscordIo for SerenityIo<'_> {
+    async fn message(&self, channel: ChannelId, message: MessageId) -> serenity::Result<Message> {
+        channel.message(self.ctx, message).await
+    }
+
+    async fn send_message(&self, channel: ChannelId, message: CreateMessage) -> serenity::Result<Message> {
+        channel.send_message(self.ctx, message).await
+    }
+
+    async fn edit_message(&self, channel: ChannelId, message: MessageId, edit: EditMessage) -> serenity::Result<()> {
+        channel.edit_message(self.ctx, message, edit).await.map(|_| ())
+    }
+
+    async fn delete_message(&self, channel: ChannelId, message: MessageId) -> serenity::Result<()> {
+        channel.delete_message(self.ctx, message).await
+    }
+
+    async fn react(&self, channel: ChannelId, message: MessageId, reaction: ReactionType) -> serenity::Result<()> {
+        channel.create_reaction(self.ctx, message, reaction).await
+    }
+
+    async fn unreact(&self, channel: ChannelId, message: MessageId, reaction: ReactionType) -> serenity::Result<()> {
+        channel.delete_reaction(self.ctx, message, None, reaction).await
+    }
+
+    async fn respond(
+        &self,
+        id: InteractionId,
+        token: &str,
+        response: CreateInteractionResponse,
+    ) -> serenity::Result<()> {
+        response.execute(self.ctx, (id, token)).await
+    }
+
+    async fn edit_response(&self, token: &str, edit: EditInteractionResponse) -> serenity::Result<Message> {
+        edit.execute(self.ctx, token).await
+    }
+
+    async fn delete_response(&self, token: &str) -> serenity::Result<()> {
+        self.ctx.http.delete_original_interaction_response(token).await
+    }
+
+    async fn followup(&self, token: &str, followup: CreateInteractionResponseFollowup) -> serenity::Result<()> {
+        followup.execute(self.ctx, (None, token)).await.map(|_| ())
+    }
+
+    async fn start_thread(
+        &self,
+        channel: ChannelId,
+        message: MessageId,
+        thread: CreateThread<'static>,
+    ) -> serenity::Result<()> {
+        channel
+            .create_thread_from_message(self.ctx, message, thread)
+            .await
+            .map(|_| ())
+    }
+
+    async fn repost(&self, channel: ChannelId, mut message: ExecuteWebhook) -> anyhow::Result<()> {
+        // webhooks belong to the parent channel of a thread
+        let webhook_channel = match channel.to_channel(self.ctx).await?.guild() {
+            Some(GuildChannel { thread_metadata: Some(_), parent_id: Some(parent), .. }) => {
+                message = message.in_thread(channel);
+                parent
+            },
+            _ => channel,
+        };
+
+        self.webhook(webhook_channel)
+            .await?
+            .execute(self.ctx, false, message)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn channel_rating(&self, channel: ChannelId) -> ChannelRating {
+        let channel = match channel.to_channel(self.ctx).await {
+            Ok(Channel::Guild(channel)) => channel,
+            Ok(Channel::Private(_)) => return ChannelRating::Dm,
+            Ok(_) => return ChannelRating::Sfw,
+            Err(e) => {
+                tracing::warn!("unable to look up channel {}, assuming it is not nsfw: {}", channel, e);
+                return ChannelRating::Sfw;
+            },
+        };
+
+        // threads inherit the age restriction of their parent channel
+        let nsfw = match (channel.thread_metadata, channel.parent_id) {
+            (Some(_), Some(parent)) => parent
+                .to_channel(self.ctx)
+                .await
+                .ok()
+                .and_then(Channel::guild)
+                .is_some_and(|parent| parent.nsfw),
+            _ => channel.nsfw,
+        };
+
+        if nsfw {
+            ChannelRating::Nsfw
+        } else {
+            ChannelRating::Sfw
+        }
+    }
+
+    async fn author_permissions(&self, msg: &Message) -> serenity::Result<Permissions> {
+        let (Some(guild), Some(member)) = (msg.guild_id, &msg.member) else {
+            return Ok(Permissions::empty());
+        };
+
+        let guild = guild.to_partial_guild(self.ctx).await?;
+        Ok(guild.partial_member_permissions(msg.author.id, member))
+    }
+}
+
+#[cfg(test)]
+pub mod recording {
+    use super::*;
+    use serde_json::Value;
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Mutex,
+        },
+    };
+
+    /// A request made to [`RecordingIo`], with builders turned into the json discord would receive
+    #[derive(Debug)]
+    #[allow(dead_code)] // kept for the debug output of failing tests
+    pub enum Call {
+        Send {
+            channel: ChannelId,
+            message: Value,
+        },
+        Edit {
+            channel: ChannelId,
+            message: MessageId,
+            edit: Value,
+        },
+        Delete {
+            channel: ChannelId,
+            message: MessageId,
+        },
+        React {
+            channel: ChannelId,
+            message: MessageId,
+            reaction: String,
+        },
+        Unreact {
+            channel: ChannelId,
+            message: MessageId,
+            reaction: String,
+        },
+        Respond {
+            id: InteractionId,
+            response: Value,
+        },
+        EditResponse {
+            edit: Value,
+        },
+        DeleteResponse,
+        Followup {
+            followup: Value,
+        },
+        StartThread {
+            channel: ChannelId,
+            message: MessageId,
+            thread: Value,
+        },
+        Repost {
+            channel: ChannelId,
+            message: Value,
+        },
+        ChannelRating {
+            channel: ChannelId,
+        },
+        AuthorPermissions {
+            message: MessageId,
+        },
+    }
+
+    /// Records what the bot asks of discord instead of sending it anywhere.
+    pub struct RecordingIo {
+        pub rating: ChannelRating,
+        pub permissions: Permissions,
+        calls: Mutex<Vec<Call>>,
+
+        /// number of messages sent so far, which make up their ids
+        sent: AtomicU64,
+
+        /// served by [`DiscordIo::message`]
+        messages: Mutex<HashMap<MessageId, Message>>,
+    }
+
+    impl RecordingIo {
+        pub fn new() -> Self {
+            Self {
+                rating: ChannelRating::Sfw,
+                permissions: Permissions::empty(),
+                calls: Mutex::new(Vec::new()),
+                sent: AtomicU64::new(0),
+                messages: Mutex::new(HashMap::new()),
+            }
+        }
+
+        /// Makes `msg` available to [`DiscordIo::message`], replacing an earlier version of it.
+        pub fn store(&self, msg: Message) {
+            self.messages.lock().unwrap().insert(msg.id, msg);
+        }
+
+        pub fn calls(&self) -> Vec<Call> {
+            std::mem::take(&mut *self.calls.lock().unwrap())
+        }
+
+        fn record(&self, call: Call) {
+            self.calls.lock().unwrap().push(call);
+        }
+    }
+
+    fn json<T: serde::Serialize>(builder: &T) -> Value {
+        serde_json::to_value(builder).unwrap()
+    }
+
+    #[async_trait]
+    impl DiscordIo for RecordingIo {
+        async fn message(&self, _channel: ChannelId, message: MessageId) -> serenity::Result<Message> {
+            self.messages
+                .lock()
+                .unwrap()
+                .get(&message)
+                .cloned()
+                .ok_or(serenity::Error::Other("unknown message"))
+        }
+
+        async fn send_message(&self, channel: ChannelId, message: CreateMessage) -> serenity::Result<Message> {
+            self.record(Call::Send { channel, message: json(&message) });
+
+            let mut sent = Message::default();
+            sent.id = MessageId::new(1001 + self.sent.fetch_add(1, Ordering::SeqCst));
+            sent.channel_id = channel;
+            Ok(sent)
+        }
+
+        async fn edit_message(
+            &self,
+            channel: ChannelId,
+            message: MessageId,
+            edit: EditMessage,
+        ) -> serenity::Result<()> {
+            self.record(Call::Edit { channel, message, edit: json(&edit) });
+            Ok(())
+        }
+
+        async fn delete_message(&self, channel: ChannelId, message: MessageId) -> serenity::Result<()> {
+            self.record(Call::Delete { channel, message });
+            Ok(())
+        }
+
+        async fn react(&self, channel: ChannelId, message: MessageId, reaction: ReactionType) -> serenity::Result<()> {
+            self.record(Call::React { channel, message, reaction: reaction.to_string() });
+            Ok(())
+        }
+
+        async fn unreact(
+            &self,
+            channel: ChannelId,
+            message: MessageId,
+            reaction: ReactionType,
+        ) -> serenity::Result<()> {
+            self.record(Call::Unreact { channel, message, reaction: reaction.to_string() });
+            Ok(())
+        }
+
+        async fn respond(
+            &self,
+            id: InteractionId,
+            _token: &str,
+            response: CreateInteractionResponse,
+        ) -> serenity::Result<()> {
+            self.record(Call::Respond { id, response: json(&response) });
+            Ok(())
+        }
+
+        async fn edit_response(&self, _token: &str, edit: EditInteractionResponse) -> serenity::Result<Message> {
+            self.record(Call::EditResponse { edit: json(&edit) });
+
+            let mut sent = Message::default();
+            sent.id = MessageId::new(999);
+            Ok(sent)
+        }
+
+        async fn delete_response(&self, _token: &str) -> serenity::Result<()> {
+            self.record(Call::DeleteResponse);
+            Ok(())
+        }
+
+        async fn followup(&self, _token: &str, followup: CreateInteractionResponseFollowup) -> serenity::Result<()> {
+            self.record(Call::Followup { followup: json(&followup) });
+            Ok(())
+        }
+
+        async fn start_thread(
+            &self,
+            channel: ChannelId,
+            message: MessageId,
+            thread: CreateThread<'static>,
+        ) -> serenity::Result<()> {
+            self.record(Call::StartThread { channel, message, thread: json(&thread) });
+            Ok(())
+        }
+
+        async fn repost(&self, channel: ChannelId, message: ExecuteWebhook) -> anyhow::Result<()> {
+            self.record(Call::Repost { channel, message: json(&message) });
+            Ok(())
+        }
+
+        async fn channel_rating(&self, channel: ChannelId) -> ChannelRating {
+            self.record(Call::ChannelRating { channel });
+            self.rating
+        }
+
+        async fn author_permissions(&self, msg: &Message) -> serenity::Result<Permissions> {
+            self.record(Call::AuthorPermissions { message: msg.id });
+            Ok(self.permissions)
+        }
+    }
+}
@@ -0,0 +1,423 @@
+use crate::post_grab_api::ContentType;
+use serde::{de::value::StrDeserializer, Deserialize, Serialize};
+use serenity::model::{
+    channel::AutoArchiveDuration,
+    id::{ChannelId, RoleId},
+    Permissions,
+};
+use std::collections::{HashMap, HashSet};
+
+/// Where a post is about to be embedded, as far as nsfw content is concerned
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChannelRating {
+    Dm,
+    Sfw,
+
+    /// an age-restricted channel, or a thread in one
+    Nsfw,
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum NsfwPolicy {
+    /// nsfw posts are hidden unless `ignore-nsfw` is set, regardless of the channel
+    #[default]
+    UserChoice,
+
+    /// nsfw posts are embedded fully in age-restricted channels and hidden everywhere else
+    ChannelFlag,
+
+    /// like `ChannelFlag`, but `ignore-nsfw` is refused outside of age-restricted channels
+    Strict,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum NsfwDecision {
+    Show,
+    Hide,
+    Refuse,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct NsfwSettings {
+    #[serde(default)]
+    pub policy: NsfwPolicy,
+
+    /// embed nsfw posts fully in direct messages
+    #[serde(default)]
+    pub allow_in_dms: bool,
+}
+
+impl NsfwSettings {
+    /// Decides how nsfw posts are embedded in a channel, `requested` being whether the user asked to see them.
+    pub fn decide(&self, rating: ChannelRating, requested: bool) -> NsfwDecision {
+        let user_choice = if requested {
+            NsfwDecision::Show
+        } else {
+            NsfwDecision::Hide
+        };
+
+        match (rating, self.policy) {
+            (ChannelRating::Dm, _) if self.allow_in_dms => NsfwDecision::Show,
+            (_, NsfwPolicy::UserChoice) => user_choice,
+            (ChannelRating::Nsfw, _) => NsfwDecision::Show,
+            (_, NsfwPolicy::ChannelFlag) => NsfwDecision::Hide,
+            (_, NsfwPolicy::Strict) if requested => NsfwDecision::Refuse,
+            (_, NsfwPolicy::Strict) => NsfwDecision::Hide,
+        }
+    }
+
+    /// Whether [`NsfwSettings::decide`] depends on the rating of the channel at all.
+    pub fn depends_on_channel(&self) -> bool {
+        self.policy != NsfwPolicy::UserChoice || self.allow_in_dms
+    }
+}
+
+/// What happens to a message after its links were embedded
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum OriginalMessageMode {
+    /// delete the message, the embed takes its place
+    #[default]
+    Delete,
+
+    /// hide the link previews of the message and reply to it with the embed
+    SuppressEmbeds,
+
+    /// leave the message as it is and reply to it with the embed
+    ReplyOnly,
+}
+
+fn default_auto_archive_duration() -> AutoArchiveDuration {
+    AutoArchiveDuration::OneDay
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ThreadSettings {
+    /// minutes until an inactive thread is archived: 60, 1440, 4320 or 10080
+    #[serde(default = "default_auto_archive_duration")]
+    pub auto_archive_duration: AutoArchiveDuration,
+}
+
+fn default_duplicate_window_secs() -> u64 {
+    24 * 60 * 60
+}
+
+fn default_duplicate_reaction() -> String {
+    "🔁".to_owned()
+}
+
+/// What happens to links to a post that was embedded recently
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum DuplicateAction {
+    /// reply with a link to the earlier embed instead of embedding the post again
+    #[default]
+    Reply,
+
+    /// embed the post again, marking the embed with a reaction
+    React,
+
+    /// leave the message alone
+    Refuse,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DuplicateSettings {
+    /// how long after an embed links to the same post count as duplicates
+    #[serde(default = "default_duplicate_window_secs")]
+    pub window_secs: u64,
+
+    #[serde(default)]
+    pub action: DuplicateAction,
+
+    /// the reaction duplicates are marked with, if `action` is `React`
+    #[serde(default = "default_duplicate_reaction")]
+    pub reaction: String,
+}
+
+/// Embeds links posted in `from` in `to`, leaving a pointer behind
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Redirect {
+    pub from: HashSet<ChannelId>,
+    pub to: ChannelId,
+}
+
+/// Reactions added to every embed, given as unicode emoji or as `<:name:id>` for custom ones
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ReactionSettings {
+    /// for embeds without a more specific set
+    #[serde(default)]
+    pub default: Vec<String>,
+
+    /// by module name, takes precedence over `default`
+    #[serde(default)]
+    pub modules: HashMap<String, Vec<String>>,
+
+    /// by content type, takes precedence over `modules`; those limited to a module over the others
+    #[serde(default)]
+    pub content_types: HashMap<ContentTypeKey, Vec<String>>,
+}
+
+impl ReactionSettings {
+    pub fn reactions(&self, module: &str, content_type: ContentType) -> &[String] {
+        let key = |module: Option<&str>| ContentTypeKey { module: module.map(str::to_owned), content_type };
+
+        self.content_types
+            .get(&key(Some(module)))
+            .or_else(|| self.content_types.get(&key(None)))
+            .or_else(|| self.modules.get(module))
+            .unwrap_or(&self.default)
+    }
+}
+
+/// A content type, limited to the posts of one module if prefixed with its name, as in `reddit:Video`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+#[serde(try_from = "String", into = "String")]
+pub struct ContentTypeKey {
+    pub module: Option<String>,
+    pub content_type: ContentType,
+}
+
+impl TryFrom<String> for ContentTypeKey {
+    type Error = serde::de::value::Error;
+
+    fn try_from(key: String) -> Result<Self, Self::Error> {
+        let (module, content_type) = match key.split_once(':') {
+            Some((module, content_type)) => (Some(module.to_owned()), content_type),
+            None => (None, key.as_str()),
+        };
+
+        let content_type = ContentType::deserialize(StrDeserializer::<Self::Error>::new(content_type))?;
+        Ok(Self { module, content_type })
+    }
+}
+
+impl From<ContentTypeKey> for String {
+    fn from(key: ContentTypeKey) -> Self {
+        // the debug output of content types is their variant name, just like their serialized form
+        match key.module {
+            Some(module) => format!("{}:{:?}", module, key.content_type),
+            None => format!("{:?}", key.content_type),
+        }
+    }
+}
+
+fn default_failure_reaction() -> String {
+    "❌".to_owned()
+}
+
+/// How failures to embed links posted in plain messages are pointed out
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FailureFeedback {
+    /// the reaction added to the message, given like in [`ReactionSettings`]
+    #[serde(default = "default_failure_reaction")]
+    pub reaction: String,
+
+    /// also reply with a button explaining what went wrong
+    #[serde(default)]
+    pub explain: bool,
+}
+
+/// The roles and permissions of a guild member who wants to use a feature
+pub struct MemberAccess<'a> {
+    pub roles: &'a [RoleId],
+    pub permissions: Permissions,
+}
+
+/// Limits a feature to members with one of `roles` or all of `permissions`
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Restriction {
+    #[serde(default)]
+    pub roles: HashSet<RoleId>,
+
+    /// permission bits as a string, e.g. "8192" for Manage Messages
+    pub permissions: Option<Permissions>,
+}
+
+impl Restriction {
+    fn allows(&self, member: &MemberAccess) -> bool {
+        self.allows_roles(member.roles) || self.permissions.is_some_and(|p| member.permissions.contains(p))
+    }
+
+    fn allows_roles(&self, roles: &[RoleId]) -> bool {
+        roles.iter().any(|role| self.roles.contains(role))
+    }
+}
+
+/// Features limited to some members of a guild. Outside of guilds everything is allowed.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Restrictions {
+    /// `/embed` options, by option name
+    #[serde(default)]
+    pub options: HashMap<String, Restriction>,
+
+    /// modules, by the name of their config section
+    #[serde(default)]
+    pub modules: HashMap<String, Restriction>,
+
+    /// embedding links posted in plain messages
+    pub auto_embed: Option<Restriction>,
+}
+
+fn allows(restriction: Option<&Restriction>, member: Option<&MemberAccess>) -> bool {
+    match (restriction, member) {
+        (Some(restriction), Some(member)) => restriction.allows(member),
+        _ => true,
+    }
+}
+
+impl Restrictions {
+    pub fn allows_option(&self, option: &str, member: Option<&MemberAccess>) -> bool {
+        allows(self.options.get(option), member)
+    }
+
+    pub fn allows_module(&self, module: &str, member: Option<&MemberAccess>) -> bool {
+        allows(self.modules.get(module), member)
+    }
+
+    pub fn allows_auto_embed(&self, member: Option<&MemberAccess>) -> bool {
+        allows(self.auto_embed.as_ref(), member)
+    }
+
+    /// Whether checking a plain message of a member with `roles` can depend on their permissions,
+    /// which message events do not include.
+    pub fn needs_message_permissions(&self, roles: &[RoleId]) -> bool {
+        self.auto_embed
+            .iter()
+            .chain(self.modules.values())
+            .any(|restriction| restriction.permissions.is_some() && !restriction.allows_roles(roles))
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct GuildSettings {
+    #[serde(default)]
+    pub nsfw: NsfwSettings,
+
+    #[serde(default)]
+    pub restrictions: Restrictions,
+
+    #[serde(default)]
+    pub original_message: OriginalMessageMode,
+
+    /// channels in which a discussion thread is started on every embed
+    #[serde(default)]
+    pub threads: HashMap<ChannelId, ThreadSettings>,
+
+    /// channels in which reactions are added to every embed
+    #[serde(default)]
+    pub reactions: HashMap<ChannelId, ReactionSettings>,
+
+    #[serde(default)]
+    pub redirects: Vec<Redirect>,
+
+    /// recognize links to posts that were embedded recently, disabled if unset
+    pub duplicates: Option<DuplicateSettings>,
+
+    /// point out links that could not be embedded, silent if unset
+    pub failure_feedback: Option<FailureFeedback>,
+}
+
+impl GuildSettings {
+    /// Where embeds of links posted in `channel` go, if elsewhere.
+    pub fn redirect_target(&self, channel: ChannelId) -> Option<ChannelId> {
+        self.redirects
+            .iter()
+            .find(|redirect| redirect.from.contains(&channel))
+            .map(|redirect| redirect.to)
+            .filter(|&to| to != channel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nsfw_decision() {
+        use ChannelRating::*;
+        use NsfwDecision::*;
+
+        let settings = |policy, allow_in_dms| NsfwSettings { policy, allow_in_dms };
+
+        // (policy, allow_in_dms, rating, requested) => decision
+        let cases = [
+            (NsfwPolicy::UserChoice, false, Sfw, false, Hide),
+            (NsfwPolicy::UserChoice, false, Sfw, true, Show),
+            (NsfwPolicy::UserChoice, false, Nsfw, false, Hide),
+            (NsfwPolicy::UserChoice, true, Dm, false, Show),
+            (NsfwPolicy::ChannelFlag, false, Sfw, true, Hide),
+            (NsfwPolicy::ChannelFlag, false, Nsfw, false, Show),
+            (NsfwPolicy::ChannelFlag, false, Dm, true, Hide),
+            (NsfwPolicy::ChannelFlag, true, Dm, false, Show),
+            (NsfwPolicy::Strict, false, Sfw, false, Hide),
+            (NsfwPolicy::Strict, false, Sfw, true, Refuse),
+            (NsfwPolicy::Strict, false, Dm, true, Refuse),
+            (NsfwPolicy::Strict, false, Nsfw, true, Show),
+        ];
+
+        for (policy, allow_in_dms, rating, requested, decision) in cases {
+            assert_eq!(
+                settings(policy, allow_in_dms).decide(rating, requested),
+                decision,
+                "{policy:?}, allow_in_dms: {allow_in_dms}, {rating:?}, requested: {requested}"
+            );
+        }
+
+        assert!(!settings(NsfwPolicy::UserChoice, false).depends_on_channel());
+        assert!(settings(NsfwPolicy::UserChoice, true).depends_on_channel());
+        assert!(settings(NsfwPolicy::ChannelFlag, false).depends_on_channel());
+    }
+
+    #[test]
+    fn reactions() {
+        let settings: ReactionSettings = serde_json::from_value(serde_json::json!({
+            "default": ["👍"],
+            "modules": { "twitter": ["🐦"] },
+            "content_types": { "Video": ["🔥"], "reddit:Video": ["🎬"] },
+        }))
+        .unwrap();
+
+        assert_eq!(settings.reactions("reddit", ContentType::Video), ["🎬"]);
+        assert_eq!(settings.reactions("twitter", ContentType::Video), ["🔥"]);
+        assert_eq!(settings.reactions("twitter", ContentType::Image), ["🐦"]);
+        assert_eq!(settings.reactions("reddit", ContentType::Image), ["👍"]);
+
+        let key = ContentTypeKey { module: Some("reddit".to_owned()), content_type: ContentType::Video };
+        assert_eq!(String::from(key.clone()), "reddit:Video");
+        assert_eq!(ContentTypeKey::try_from("reddit:Video".to_owned()).unwrap(), key);
+        assert!(ContentTypeKey::try_from("reddit:Movie".to_owned()).is_err());
+    }
+
+    #[test]
+    fn restrictions() {
+        let restriction = Restriction {
+            roles: HashSet::from([RoleId::new(1)]),
+            permissions: Some(Permissions::MANAGE_MESSAGES | Permissions::EMBED_LINKS),
+        };
+        let restrictions = Restrictions {
+            modules: HashMap::from([("reddit".to_owned(), restriction)]),
+            ..Default::default()
+        };
+
+        fn member(roles: &[RoleId], permissions: Permissions) -> MemberAccess<'_> {
+            MemberAccess { roles, permissions }
+        }
+        let allowed = |member: &MemberAccess| restrictions.allows_module("reddit", Some(member));
+
+        // one of the roles or all of the permissions
+        assert!(allowed(&member(
+            &[RoleId::new(2), RoleId::new(1)],
+            Permissions::empty()
+        )));
+        assert!(allowed(&member(&[], Permissions::all())));
+        assert!(!allowed(&member(&[RoleId::new(2)], Permissions::MANAGE_MESSAGES)));
+
+        // unrestricted features, and everything outside of guilds
+        assert!(restrictions.allows_module("twitter", Some(&member(&[], Permissions::empty()))));
+        assert!(restrictions.allows_module("reddit", None));
+        assert!(restrictions.allows_auto_embed(Some(&member(&[], Permissions::empty()))));
+
+        assert!(restrictions.needs_message_permissions(&[RoleId::new(2)]));
+        assert!(!restrictions.needs_message_permissions(&[RoleId::new(1)]));
+        assert!(!Restrictions::default().needs_message_permissions(&[]));
+    }
+}
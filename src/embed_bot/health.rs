@@ -0,0 +1,305 @@
+use crate::post_grab_api::{Error, Post, ScrapeError};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+fn default_failure_threshold() -> u32 {
+    5
+}
+
+fn default_open_secs() -> u64 {
+    60
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CircuitBreakerSettings {
+    /// consecutive failures after which a module is paused
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+
+    /// how long a module stays paused, in seconds
+    #[serde(default = "default_open_secs")]
+    pub open_secs: u64,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+enum CircuitState {
+    /// the module is called as usual
+    #[default]
+    Closed,
+
+    /// the module failed repeatedly and is not called until the deadline
+    Open { until: Instant },
+
+    /// a single call is trying whether the module works again
+    HalfOpen,
+}
+
+#[derive(Default)]
+struct ModuleHealth {
+    successes: u64,
+    failures: u64,
+    consecutive_failures: u32,
+
+    /// number of failures by kind
+    errors: HashMap<String, u64>,
+    last_error: Option<(SystemTime, String)>,
+    state: CircuitState,
+}
+
+/// How a call to a module turned out, as far as its health is concerned
+enum Outcome {
+    Success,
+    Failure(String),
+
+    /// the module was not called or the result says nothing about it
+    Neutral,
+}
+
+fn outcome(res: &Result<Box<dyn Post>, Error>) -> Outcome {
+    match res {
+        Ok(_) | Err(Error::NotSupposedToEmbed(_)) => Outcome::Success,
+
+        // the module did its job, the post itself is the problem
+        Err(Error::PostFetchFailed {
+            kind: ScrapeError::NotFound | ScrapeError::Private | ScrapeError::TooLarge,
+            ..
+        }) => Outcome::Success,
+        Err(Error::PostFetchFailed { kind, .. }) => Outcome::Failure(kind.to_string()),
+        Err(Error::TimedOut(_)) => Outcome::Failure(ScrapeError::Timeout.to_string()),
+        Err(_) => Outcome::Neutral,
+    }
+}
+
+/// Tracks how well each module works and pauses the ones that keep failing.
+pub struct HealthTracker {
+    breaker: Option<CircuitBreakerSettings>,
+    modules: Mutex<HashMap<&'static str, ModuleHealth>>,
+}
+
+impl HealthTracker {
+    pub fn new() -> Self {
+        Self { breaker: None, modules: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn set_circuit_breaker(&mut self, settings: CircuitBreakerSettings) {
+        self.breaker = Some(settings);
+    }
+
+    /// Whether `module` may be called, letting a trial call through once its pause is over.
+    pub fn allow(&self, module: &'static str) -> bool {
+        let mut modules = self.modules.lock().unwrap();
+        let health = modules.entry(module).or_default();
+
+        match health.state {
+            CircuitState::Closed => true,
+            CircuitState::Open { until } if until <= Instant::now() => {
+                tracing::info!("trying {} again", module);
+                health.state = CircuitState::HalfOpen;
+                true
+            },
+            CircuitState::Open { .. } | CircuitState::HalfOpen => false,
+        }
+    }
+
+    /// Records the result of calling `module`.
+    pub fn record(&self, module: &'static str, res: &Result<Box<dyn Post>, Error>) {
+        let mut modules = self.modules.lock().unwrap();
+        let health = modules.entry(module).or_default();
+
+        match outcome(res) {
+            Outcome::Success => {
+                if matches!(health.state, CircuitState::HalfOpen) {
+                    tracing::info!("{} works again, closing circuit", module);
+                }
+
+                health.successes += 1;
+                health.consecutive_failures = 0;
+                health.state = CircuitState::Closed;
+            },
+            Outcome::Failure(kind) => {
+                health.failures += 1;
+                health.consecutive_failures += 1;
+                *health.errors.entry(kind.clone()).or_default() += 1;
+
+                if let Err(e) = res {
+                    health.last_error = Some((SystemTime::now(), e.to_string()));
+                }
+
+                let Some(breaker) = &self.breaker else {
+                    return;
+                };
+
+                let tripped = health.consecutive_failures >= breaker.failure_threshold;
+
+                if tripped || matches!(health.state, CircuitState::HalfOpen) {
+                    tracing::warn!(
+                        "{} failed {} times in a row ({}), not calling it for {}s",
+                        module,
+                        health.consecutive_failures,
+                        kind,
+                        breaker.open_secs
+                    );
+
+                    health.state =
+                        CircuitState::Open { until: Instant::now() + Duration::from_secs(breaker.open_secs) };
+                }
+            },
+            Outcome::Neutral => {
+                // give the next call the chance to finish the trial
+                if matches!(health.state, CircuitState::HalfOpen) {
+                    health.state = CircuitState::Open { until: Instant::now() };
+                }
+            },
+        }
+    }
+
+    /// A markdown summary of the health of `modules`, a paragraph each.
+    pub fn report<'a>(&self, modules: impl IntoIterator<Item = &'a str>) -> String {
+        let health = self.modules.lock().unwrap();
+        let mut report = String::new();
+
+        for module in modules {
+            let Some(health) = health.get(module) else {
+                let _ = writeln!(report, "**{module}**: 🟢 not used yet");
+                continue;
+            };
+
+            let state = match health.state {
+                CircuitState::Closed if health.consecutive_failures > 0 => "🟡 failing".to_owned(),
+                CircuitState::Closed => "🟢 ok".to_owned(),
+                CircuitState::Open { until } => {
+                    format!(
+                        "🔴 paused for {}s",
+                        until.saturating_duration_since(Instant::now()).as_secs()
+                    )
+                },
+                CircuitState::HalfOpen => "🟡 being tried again".to_owned(),
+            };
+
+            let calls = health.successes + health.failures;
+            let error_rate = if calls == 0 {
+                0.0
+            } else {
+                health.failures as f64 * 100.0 / calls as f64
+            };
+
+            let _ = write!(report, "**{module}**: {state}, {calls} calls, {error_rate:.1}% failed");
+
+            if !health.errors.is_empty() {
+                let errors = health
+                    .errors
+                    .iter()
+                    .map(|(kind, n)| format!("{kind}: {n}"))
+                    .collect::<Vec<_>>();
+                let _ = write!(report, " ({})", errors.join(", "));
+            }
+
+            if let Some((at, e)) = &health.last_error {
+                let at = at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+                let _ = write!(report, "\nlast error <t:{at}:R>: {e}");
+            }
+
+            report.push('\n');
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failed(kind: ScrapeError) -> Result<Box<dyn Post>, Error> {
+        Err(Error::PostFetchFailed { kind, source: anyhow::anyhow!("failed") })
+    }
+
+    fn tracker(failure_threshold: u32) -> HealthTracker {
+        let mut tracker = HealthTracker::new();
+        tracker.set_circuit_breaker(CircuitBreakerSettings { failure_threshold, open_secs: 60 });
+        tracker
+    }
+
+    /// Lets the open circuit of `module` time out.
+    fn expire(tracker: &HealthTracker, module: &'static str) {
+        let mut modules = tracker.modules.lock().unwrap();
+        let health = modules.get_mut(module).unwrap();
+        assert!(matches!(health.state, CircuitState::Open { .. }));
+        health.state = CircuitState::Open { until: Instant::now() };
+    }
+
+    #[test]
+    fn trip() {
+        let tracker = tracker(2);
+
+        tracker.record("a", &failed(ScrapeError::LayoutChanged));
+        assert!(tracker.allow("a"));
+
+        // a missing post is not the fault of the module
+        tracker.record("a", &failed(ScrapeError::NotFound));
+        tracker.record("a", &failed(ScrapeError::LayoutChanged));
+        assert!(tracker.allow("a"));
+
+        tracker.record("a", &failed(ScrapeError::Other));
+        assert!(!tracker.allow("a"));
+        assert!(tracker.allow("b"));
+    }
+
+    #[test]
+    fn without_breaker() {
+        let tracker = HealthTracker::new();
+
+        for _ in 0..10 {
+            tracker.record("a", &failed(ScrapeError::LayoutChanged));
+        }
+
+        assert!(tracker.allow("a"));
+        assert!(tracker.report(["a"]).contains("🟡 failing, 10 calls, 100.0% failed"));
+    }
+
+    #[test]
+    fn half_open() {
+        let tracker = tracker(2);
+        tracker.record("a", &failed(ScrapeError::Timeout));
+        tracker.record("a", &failed(ScrapeError::Timeout));
+
+        // once the circuit timed out, a single trial call is let through
+        expire(&tracker, "a");
+        assert!(tracker.allow("a"));
+        assert!(!tracker.allow("a"));
+        assert!(tracker.report(["a"]).contains("being tried again"));
+
+        // failing the trial opens the circuit again right away
+        tracker.record("a", &failed(ScrapeError::Timeout));
+        assert!(!tracker.allow("a"));
+
+        expire(&tracker, "a");
+        assert!(tracker.allow("a"));
+        tracker.record("a", &failed(ScrapeError::Private));
+        assert!(tracker.allow("a"));
+        assert!(tracker.allow("a"));
+
+        // and the count of failures starts over
+        tracker.record("a", &failed(ScrapeError::Timeout));
+        assert!(tracker.allow("a"));
+    }
+
+    #[test]
+    fn neutral_trial() {
+        let tracker = tracker(1);
+        tracker.record("a", &failed(ScrapeError::RateLimited));
+
+        expire(&tracker, "a");
+        assert!(tracker.allow("a"));
+
+        // a trial that never reached the module leaves the trial to the next call
+        tracker.record("a", &Err(Error::QueueFull("a")));
+        assert!(tracker.allow("a"));
+        assert!(!tracker.allow("a"));
+    }
+}
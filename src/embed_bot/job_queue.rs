@@ -0,0 +1,229 @@
+use crate::post_grab_api::{Error, JOB_GUARD};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    future::Future,
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::sync::Semaphore;
+
+fn default_workers() -> NonZeroUsize {
+    NonZeroUsize::new(4).unwrap()
+}
+
+fn default_max_queued() -> usize {
+    16
+}
+
+fn default_timeout_secs() -> u64 {
+    60
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct QueueLimits {
+    /// how many posts are fetched at the same time
+    #[serde(default = "default_workers")]
+    pub workers: NonZeroUsize,
+
+    /// how many posts may wait for a worker before new ones are turned away
+    #[serde(default = "default_max_queued")]
+    pub max_queued: usize,
+
+    /// how long fetching a post may take before it is cancelled
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+impl Default for QueueLimits {
+    fn default() -> Self {
+        Self {
+            workers: default_workers(),
+            max_queued: default_max_queued(),
+            timeout_secs: default_timeout_secs(),
+        }
+    }
+}
+
+/// Every module has its own workers, `modules` overrides the limits of specific ones
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct JobQueueSettings {
+    #[serde(flatten)]
+    pub default: QueueLimits,
+
+    /// by module name
+    #[serde(default)]
+    pub modules: HashMap<String, QueueLimits>,
+}
+
+struct ModuleQueue {
+    workers: Arc<Semaphore>,
+
+    /// jobs waiting for or holding a worker
+    jobs: AtomicUsize,
+    capacity: usize,
+    timeout: Duration,
+}
+
+impl ModuleQueue {
+    fn new(limits: &QueueLimits) -> Self {
+        Self {
+            workers: Arc::new(Semaphore::new(limits.workers.get())),
+            jobs: AtomicUsize::new(0),
+            capacity: limits.workers.get() + limits.max_queued,
+            timeout: Duration::from_secs(limits.timeout_secs),
+        }
+    }
+
+    fn reserve(&self) -> Option<Slot<'_>> {
+        self.jobs
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |jobs| {
+                (jobs < self.capacity).then_some(jobs + 1)
+            })
+            .ok()
+            .map(|_| Slot(&self.jobs))
+    }
+}
+
+/// A place in a module queue, given back when dropped
+struct Slot<'a>(&'a AtomicUsize);
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Runs the jobs of each module on a limited number of workers,
+/// so that a slow module cannot hold up the others.
+pub struct JobQueue {
+    settings: JobQueueSettings,
+    queues: Mutex<HashMap<&'static str, Arc<ModuleQueue>>>,
+}
+
+impl JobQueue {
+    pub fn from_settings(settings: JobQueueSettings) -> Self {
+        Self { settings, queues: Mutex::new(HashMap::new()) }
+    }
+
+    fn queue(&self, module: &'static str) -> Arc<ModuleQueue> {
+        let mut queues = self.queues.lock().unwrap();
+
+        let queue = queues.entry(module).or_insert_with(|| {
+            let limits = self.settings.modules.get(module).unwrap_or(&self.settings.default);
+            Arc::new(ModuleQueue::new(limits))
+        });
+
+        Arc::clone(queue)
+    }
+
+    /// Waits for a worker of `module` and runs `job` on it, the timeout covers both.
+    /// The job is cancelled if it times out or the returned future is dropped,
+    /// but blocking work it started keeps the worker busy until it is done, see [`JOB_GUARD`].
+    pub async fn run<F: Future>(&self, module: &'static str, job: F) -> Result<F::Output, Error> {
+        let queue = self.queue(module);
+        let _slot = queue.reserve().ok_or(Error::QueueFull(module))?;
+
+        let job = async {
+            let worker = Arc::clone(&queue.workers)
+                .acquire_owned()
+                .await
+                .expect("worker semaphore is never closed");
+
+            JOB_GUARD.scope(Arc::new(worker), job).await
+        };
+
+        tokio::time::timeout(queue.timeout, job)
+            .await
+            .map_err(|_| Error::TimedOut(module))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::post_grab_api::spawn_blocking;
+    use std::sync::atomic::AtomicBool;
+
+    fn job_queue(workers: usize, max_queued: usize, timeout_secs: u64) -> Arc<JobQueue> {
+        let limits = QueueLimits { workers: NonZeroUsize::new(workers).unwrap(), max_queued, timeout_secs };
+        Arc::new(JobQueue::from_settings(JobQueueSettings {
+            default: limits,
+            modules: HashMap::new(),
+        }))
+    }
+
+    #[test]
+    fn no_workers() {
+        assert!(serde_json::from_str::<QueueLimits>(r#"{ "workers": 0 }"#).is_err());
+        assert_eq!(serde_json::from_str::<QueueLimits>("{}").unwrap().workers.get(), 4);
+    }
+
+    #[tokio::test]
+    async fn queue_limit() {
+        let queue = job_queue(1, 1, 60);
+        let release = Arc::new(Semaphore::new(0));
+        let started = Arc::new(AtomicUsize::new(0));
+
+        let spawn_job = || {
+            let (queue, release, started) = (queue.clone(), release.clone(), started.clone());
+
+            tokio::spawn(async move {
+                queue
+                    .run("a", async move {
+                        started.fetch_add(1, Ordering::SeqCst);
+                        release.acquire().await.unwrap().forget();
+                    })
+                    .await
+            })
+        };
+
+        let jobs = [spawn_job(), spawn_job()];
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // one job holds the only worker, the other one waits for it and there is no room for more
+        assert_eq!(started.load(Ordering::SeqCst), 1);
+        assert!(matches!(queue.run("a", async {}).await, Err(Error::QueueFull("a"))));
+
+        // other modules have workers of their own
+        assert!(queue.run("b", async {}).await.is_ok());
+
+        release.add_permits(2);
+        for job in jobs {
+            job.await.unwrap().unwrap();
+        }
+
+        assert_eq!(started.load(Ordering::SeqCst), 2);
+        assert!(queue.run("a", async {}).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn timeout() {
+        let queue = job_queue(1, 1, 1);
+        let done = Arc::new(AtomicBool::new(false));
+
+        let job = {
+            let done = done.clone();
+
+            async move {
+                spawn_blocking(move || {
+                    std::thread::sleep(Duration::from_millis(2500));
+                    done.store(true, Ordering::SeqCst);
+                })
+                .await
+            }
+        };
+
+        assert!(matches!(queue.run("a", job).await, Err(Error::TimedOut("a"))));
+
+        // the blocking work outlives its job and keeps the only worker busy until it is done,
+        // waiting for the worker counts towards the timeout
+        assert!(matches!(queue.run("a", async {}).await, Err(Error::TimedOut("a"))));
+        let finished = queue.run("a", async { done.load(Ordering::SeqCst) }).await.unwrap();
+        assert!(finished);
+    }
+}
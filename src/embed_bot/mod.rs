@@ -1,227 +1,2620 @@
+mod content_type_probe;
+mod discord_io;
+mod guild_settings;
+mod health;
+mod job_queue;
+mod post_cache;
+mod rate_limit;
 mod settings;
+mod store;
+mod ttl_cache;
 
-use crate::post_grab_api::{CreateResponse, DynPostScraper, EmbedOptions, Error, Post};
+use crate::post_grab_api::{
+    gallery::{pager_buttons, PagerState, PAGER_NEXT_ID, PAGER_PREV_ID},
+    limit_descr_len, strip_markdown, CreateResponse, DynPostScraper, EmbedOptions, Error, Post, ScrapeError,
+    EMBED_FIELD_MAX_LEN,
+};
+use anyhow::Context as _;
+use content_type_probe::ContentTypeProbe;
+pub use content_type_probe::ContentTypeProbeSettings;
+use discord_io::{DiscordIo, SerenityIo};
+pub use guild_settings::GuildSettings;
+use guild_settings::{
+    ChannelRating, DuplicateAction, DuplicateSettings, MemberAccess, NsfwDecision, OriginalMessageMode, Restrictions,
+};
+pub use health::CircuitBreakerSettings;
+use health::HealthTracker;
 use itertools::Itertools;
+use job_queue::JobQueue;
+pub use job_queue::JobQueueSettings;
+use post_cache::PostCache;
+pub use post_cache::PostCacheSettings;
+pub use rate_limit::RateLimitSettings;
+use rate_limit::{RateLimiter, Scope};
+use serde::{Deserialize, Serialize};
 use serenity::{
     async_trait,
     builder::{
-        CreateCommand, CreateCommandOption, CreateEmbed, CreateInteractionResponse, CreateInteractionResponseMessage,
-        CreateMessage,
+        CreateActionRow, CreateAllowedMentions, CreateButton, CreateCommand, CreateCommandOption, CreateEmbed,
+        CreateInputText, CreateInteractionResponse, CreateInteractionResponseFollowup,
+        CreateInteractionResponseMessage, CreateMessage, CreateModal, CreateThread, EditInteractionResponse,
+        EditMessage, ExecuteWebhook,
     },
     client::{Context, EventHandler},
     model::{
-        application::{Command, CommandData, CommandOptionType, CommandType, Interaction},
-        channel::Message,
+        application::{ActionRowComponent, ButtonStyle, InputTextStyle, ModalInteraction},
+        application::{Command, CommandInteraction, CommandOptionType, CommandType, ComponentInteraction, Interaction},
+        channel::{Message, ReactionType},
+        event::MessageUpdateEvent,
         gateway::Ready,
+        id::{ChannelId, GuildId, MessageId, UserId},
+        user::User,
+        Permissions,
     },
+    prelude::Mentionable,
 };
 pub use settings::Settings;
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use store::PersistentMap;
+use ttl_cache::TtlCache;
 use url::Url;
 
-#[derive(Default)]
+/// Name under which content type probes are rate limited and queued, like a module
+const PROBE_MODULE: &str = "content_type_probe";
+
+/// Maximum number of gallery messages whose pagination state is remembered
+const PAGER_CAPACITY: usize = 4096;
+
+/// Maximum number of kept messages whose edits and deletions are followed
+const REPLY_CAPACITY: usize = 4096;
+
+/// Maximum number of embedded posts remembered to recognize duplicates
+const SEEN_POST_CAPACITY: usize = 16384;
+
+const REVEAL_ID: &str = "reveal";
+const UNDO_ID: &str = "undo";
+const EDIT_COMMENT_ID: &str = "edit-comment";
+const COMMENT_MODAL_ID: &str = "comment-modal";
+const COMMENT_INPUT_ID: &str = "comment";
+const EXPLAIN_FAILURE_ID: &str = "explain-failure";
+
+/// How long the buttons of an embed keep working after it was sent
+const EMBED_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Maximum number of embeds whose buttons are kept working
+const EMBED_CAPACITY: usize = 1024;
+
+/// How long the permissions of message authors are remembered
+const PERMISSION_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Maximum number of message authors whose permissions are remembered
+const PERMISSION_CAPACITY: usize = 1024;
+
+/// Maximum length of thread names, in characters
+const THREAD_NAME_MAX_LEN: usize = 100;
+
+/// A message that was deleted in favour of an embed
+#[derive(Clone)]
+struct Original {
+    content: String,
+    username: String,
+    avatar_url: String,
+}
+
+/// A message that was kept next to its embed
+#[derive(Clone, Copy)]
+struct Kept {
+    message: MessageId,
+
+    /// whether its link previews were suppressed
+    suppressed: bool,
+}
+
+/// Everything about an embed besides the post itself
+#[derive(Clone)]
+struct EmbedContext {
+    author: User,
+
+    /// name of the module the post was scraped by
+    module: &'static str,
+
+    /// the channel the post was shared in
+    source_channel: ChannelId,
+
+    /// the message pointing to the embed from `source_channel`, if it was redirected
+    pointer: Option<MessageId>,
+
+    opts: EmbedOptions,
+
+    /// the options the post is revealed with
+    reveal_opts: EmbedOptions,
+
+    /// whether the author can take the embed back
+    undoable: bool,
+
+    /// the message the embed replaced, re-posted on undo; `None` if it was kept
+    original: Option<Original>,
+
+    /// the message the embed was created from, if it was kept
+    kept: Option<Kept>,
+}
+
+impl EmbedContext {
+    /// Whether viewers can reveal what the embed of `post` hides.
+    fn can_reveal(&self, post: &dyn Post) -> bool {
+        post.is_hidden(&self.opts) && !post.is_hidden(&self.reveal_opts)
+    }
+
+    fn set_comment(&mut self, comment: Option<String>) {
+        self.opts.comment.clone_from(&comment);
+        self.reveal_opts.comment = comment;
+    }
+}
+
+/// The embed sent in reply to a message that was kept, or how it was pointed out that it could not be embedded
+#[derive(Clone, Serialize, Deserialize)]
+struct Reply {
+    channel: ChannelId,
+
+    /// the embed or the note about the failure, none if the failure was only pointed out by a reaction
+    message: Option<MessageId>,
+
+    /// the message pointing to the embed, if it was redirected to another channel
+    pointer: Option<MessageId>,
+
+    /// the content of the original message the embed was created from
+    content: String,
+
+    /// whether the link previews of the original message were suppressed
+    #[serde(default)]
+    suppressed: bool,
+
+    /// whether the reply only says that the link could not be embedded
+    #[serde(default)]
+    failed: bool,
+
+    /// the reaction added to the original message because it could not be embedded
+    #[serde(default)]
+    reaction: Option<ReactionType>,
+}
+
+/// Where a post was embedded, remembered to recognize links to the same post
+#[derive(Clone, Serialize, Deserialize)]
+struct SeenPost {
+    channel: ChannelId,
+    message: MessageId,
+    posted_at: SystemTime,
+}
+
+/// What the links in a message turned into
+#[cfg(feature = "implicit-auto-embed")]
+enum Embedding {
+    Post(Arc<dyn Post>, Box<EmbedContext>),
+
+    /// the message is not supposed to be embedded
+    Nothing,
+
+    /// the message should be embedded, but fetching the post failed
+    Failed(Error),
+
+    /// the post cannot be fetched right now, but may be later
+    Postponed,
+}
+
+/// Splits a message into the url to embed and the comment around it.
+#[cfg(feature = "implicit-auto-embed")]
+fn parse_message_content(content: &str) -> (Option<Url>, Option<String>) {
+    let content: Vec<_> = content.lines().collect();
+
+    match &content[..] {
+        [] => (None, None),
+        [a] => (Url::parse(a).ok(), None),
+        args => {
+            let (urls, comments): (Vec<_>, Vec<_>) = args
+                .iter()
+                .filter(|s| !s.is_empty())
+                .partition(|a| Url::parse(a).is_ok());
+
+            let mut urls = urls.into_iter().filter_map(|u| Url::parse(u).ok());
+
+            let comments: String = Itertools::intersperse(comments.into_iter(), "\n").collect();
+
+            (urls.next(), Some(comments))
+        },
+    }
+}
+
+/// An embed sent by the bot, kept around to handle its buttons
+#[derive(Clone)]
+struct TrackedEmbed {
+    post: Arc<dyn Post>,
+    context: EmbedContext,
+}
+
 pub struct EmbedBot {
     apis: Vec<Box<dyn DynPostScraper + Send + Sync>>,
+    content_type_probe: Option<ContentTypeProbe>,
+    rate_limiter: Option<RateLimiter>,
+    job_queue: Option<JobQueue>,
+    post_cache: Option<PostCache>,
+    health: HealthTracker,
+    pagers: PersistentMap<MessageId, PagerState>,
+    replies: PersistentMap<MessageId, Reply>,
+
+    /// by guild and canonical url of the post
+    seen_posts: PersistentMap<(GuildId, String), SeenPost>,
+    embeds: TtlCache<MessageId, TrackedEmbed>,
+
+    /// why embedding failed, by the message explaining it
+    failures: TtlCache<MessageId, ScrapeError>,
+
+    /// permissions of message authors, by guild and user
+    #[cfg_attr(not(feature = "implicit-auto-embed"), allow(dead_code))]
+    permissions: TtlCache<(GuildId, UserId), Permissions>,
+    guild_defaults: GuildSettings,
+    guilds: HashMap<GuildId, GuildSettings>,
+}
+
+impl Default for EmbedBot {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl EmbedBot {
     pub fn new() -> Self {
-        EmbedBot { apis: Vec::new() }
+        EmbedBot {
+            apis: Vec::new(),
+            content_type_probe: None,
+            rate_limiter: None,
+            job_queue: None,
+            post_cache: None,
+            health: HealthTracker::new(),
+            pagers: PersistentMap::in_memory(PAGER_CAPACITY),
+            replies: PersistentMap::in_memory(REPLY_CAPACITY),
+            seen_posts: PersistentMap::in_memory(SEEN_POST_CAPACITY),
+            embeds: TtlCache::new(EMBED_TTL, EMBED_CAPACITY),
+            failures: TtlCache::new(EMBED_TTL, EMBED_CAPACITY),
+            permissions: TtlCache::new(PERMISSION_TTL, PERMISSION_CAPACITY),
+            guild_defaults: GuildSettings::default(),
+            guilds: HashMap::new(),
+        }
     }
 
-    pub fn find_api(&self, url: &Url) -> Option<&(dyn DynPostScraper + Send + Sync)> {
-        self.apis.iter().find(|a| a.is_suitable(url)).map(AsRef::as_ref)
+    /// Keeps state that has to survive restarts, like gallery pagination, in `dir`.
+    pub fn set_state_dir(&mut self, dir: &Path) -> anyhow::Result<()> {
+        std::fs::create_dir_all(dir).with_context(|| format!("Unable to create {}", dir.display()))?;
+        self.pagers = PersistentMap::load(dir.join("pagers.json"), PAGER_CAPACITY)?;
+        self.replies = PersistentMap::load(dir.join("replies.json"), REPLY_CAPACITY)?;
+        self.seen_posts = PersistentMap::load(dir.join("seen_posts.json"), SEEN_POST_CAPACITY)?;
+        Ok(())
+    }
+
+    /// Finds the module for `url`, probing its content type if no module claims it by its path.
+    /// Probing asks the server of the url, so it is subject to rate limits and the job queue like scraping.
+    async fn find_api(&self, url: &Url, scope: &Scope) -> Result<&(dyn DynPostScraper + Send + Sync), Error> {
+        if let Some(api) = self.apis.iter().find(|a| a.is_suitable(url)) {
+            return Ok(api.as_ref());
+        }
+
+        let Some(probe) = &self.content_type_probe else {
+            return Err(Error::NoApiAvailable);
+        };
+
+        let content_type = match probe.cached(url) {
+            Some(content_type) => content_type,
+            None => {
+                if let Some(rate_limiter) = &self.rate_limiter {
+                    rate_limiter.acquire(PROBE_MODULE, scope).map_err(Error::RateLimited)?;
+                }
+
+                match &self.job_queue {
+                    Some(job_queue) => job_queue.run(PROBE_MODULE, probe.content_type(url)).await?,
+                    None => probe.content_type(url).await,
+                }
+            },
+        };
+
+        content_type
+            .and_then(|content_type| self.apis.iter().find(|a| a.is_suitable_content_type(&content_type)))
+            .map(|api| api.as_ref() as _)
+            .ok_or(Error::NoApiAvailable)
     }
 
     pub fn register_api<T: 'static + DynPostScraper + Send + Sync>(&mut self, api: T) {
         self.apis.push(Box::new(api));
     }
 
-    async fn get_post(&self, mut url: Url) -> Result<Box<dyn Post>, Error> {
-        if let Some(api) = self.find_api(&url) {
-            url.set_fragment(None);
-            api.get_dyn_post(url).await
+    pub fn enable_content_type_probe(&mut self, settings: ContentTypeProbeSettings) {
+        self.content_type_probe = Some(ContentTypeProbe::from_settings(settings));
+    }
+
+    pub fn enable_rate_limits(&mut self, settings: RateLimitSettings) {
+        self.rate_limiter = Some(RateLimiter::from_settings(settings));
+    }
+
+    pub fn enable_job_queue(&mut self, settings: JobQueueSettings) {
+        self.job_queue = Some(JobQueue::from_settings(settings));
+    }
+
+    pub fn enable_post_cache(&mut self, settings: PostCacheSettings) -> anyhow::Result<()> {
+        self.post_cache = Some(PostCache::from_settings(settings)?);
+        Ok(())
+    }
+
+    pub fn enable_circuit_breaker(&mut self, settings: CircuitBreakerSettings) {
+        self.health.set_circuit_breaker(settings);
+    }
+
+    pub fn set_guild_settings(&mut self, defaults: GuildSettings, guilds: HashMap<GuildId, GuildSettings>) {
+        self.guild_defaults = defaults;
+        self.guilds = guilds;
+    }
+
+    fn guild_settings(&self, guild: Option<GuildId>) -> &GuildSettings {
+        guild
+            .and_then(|guild| self.guilds.get(&guild))
+            .unwrap_or(&self.guild_defaults)
+    }
+
+    /// Applies the nsfw policy of `guild` to `opts` for an embed in `channel`.
+    /// Returns the options to embed with and the options to reveal the post with,
+    /// or `None` if the user asked for something the policy does not allow.
+    async fn apply_nsfw_policy(
+        &self,
+        io: &dyn DiscordIo,
+        guild: Option<GuildId>,
+        channel: ChannelId,
+        opts: EmbedOptions,
+    ) -> Option<(EmbedOptions, EmbedOptions)> {
+        let nsfw = &self.guild_settings(guild).nsfw;
+
+        // looking up the channel takes a request, so it is skipped if the answer does not matter
+        let rating = if nsfw.depends_on_channel() {
+            io.channel_rating(channel).await
         } else {
-            Err(Error::NoApiAvailable)
+            ChannelRating::Sfw
+        };
+
+        let ignore_nsfw = match nsfw.decide(rating, opts.ignore_nsfw) {
+            NsfwDecision::Show => true,
+            NsfwDecision::Hide => false,
+            NsfwDecision::Refuse => return None,
+        };
+
+        let reveal_opts = EmbedOptions {
+            ignore_nsfw: nsfw.decide(rating, true) == NsfwDecision::Show,
+            ignore_spoiler: true,
+            ..opts.clone()
+        };
+
+        Some((EmbedOptions { ignore_nsfw, ..opts }, reveal_opts))
+    }
+
+    /// Blurs the preview of `post` to show in its place, if the embed created with `opts` hides it.
+    #[cfg_attr(not(feature = "blur"), allow(unused_variables))]
+    async fn blur_hidden(&self, module: &str, post: &dyn Post, opts: EmbedOptions) -> EmbedOptions {
+        #[cfg(feature = "blur")]
+        if let (true, Some(settings), Some(preview)) = (
+            post.is_hidden(&opts),
+            self.apis
+                .iter()
+                .find(|api| api.name() == module)
+                .and_then(|api| api.blur_settings()),
+            post.preview_image(),
+        ) {
+            match crate::post_grab_api::blur::blurred_preview(preview.clone(), settings, "preview.png").await {
+                Ok(attachment) => return EmbedOptions { blurred_preview: Some(attachment), ..opts },
+                Err(e) => tracing::warn!("unable to blur preview {}: {:?}", preview, e),
+            }
+        }
+
+        opts
+    }
+
+    async fn get_post(
+        &self,
+        mut url: Url,
+        restrictions: &Restrictions,
+        member: Option<&MemberAccess<'_>>,
+        scope: &Scope,
+    ) -> Result<(Arc<dyn Post>, &'static str), Error> {
+        let api = self.find_api(&url, scope).await?;
+
+        if !restrictions.allows_module(api.name(), member) {
+            return Err(Error::ModuleNotAllowed(api.name()));
+        }
+
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.acquire(api.name(), scope).map_err(Error::RateLimited)?;
+        }
+
+        url.set_fragment(None);
+
+        if let Some(cache) = &self.post_cache {
+            if let Some(post) = cache.get(&url, |value| api.decode_post(value)).await {
+                return Ok((post, api.name()));
+            }
+        }
+
+        if !self.health.allow(api.name()) {
+            return Err(Error::ModuleUnavailable(api.name()));
+        }
+
+        let fetched = match &self.job_queue {
+            Some(job_queue) => job_queue
+                .run(api.name(), api.get_dyn_post(url.clone()))
+                .await
+                .and_then(|res| res),
+            None => api.get_dyn_post(url.clone()).await,
+        };
+
+        self.health.record(api.name(), &fetched);
+        let post: Arc<dyn Post> = fetched?.into();
+
+        if let Some(cache) = &self.post_cache {
+            cache.insert(api.name(), &url, Arc::clone(&post)).await;
         }
+
+        Ok((post, api.name()))
+    }
+
+    fn error_embed(msg: &str) -> CreateEmbed {
+        CreateEmbed::new().title(":x: Error").description(msg)
     }
 
     fn reply_error(msg: &str, response: CreateResponse) -> CreateResponse {
-        response.embed(CreateEmbed::new().title(":x: Error").description(msg))
+        response.embed(Self::error_embed(msg))
     }
-}
 
-#[async_trait]
-impl EventHandler for EmbedBot {
-    #[cfg(feature = "implicit-auto-embed")]
-    async fn message(&self, ctx: Context, msg: Message) {
-        if !msg.author.bot {
-            let content: Vec<_> = msg.content.lines().collect();
+    async fn reply_ephemeral_error(io: &dyn DiscordIo, command: &CommandInteraction, msg: &str) {
+        let response = CreateInteractionResponse::Message(
+            Self::reply_error(
+                msg,
+                CreateResponse::Interaction(CreateInteractionResponseMessage::new().ephemeral(true)),
+            )
+            .into_interaction(),
+        );
+
+        if let Err(e) = io.respond(command.id, &command.token, response).await {
+            tracing::error!("unable to send error: {}", e);
+        }
+    }
+
+    /// Fills in the deferred response to `command` with an error.
+    async fn reply_deferred_error(io: &dyn DiscordIo, command: &CommandInteraction, msg: &str) {
+        let edit = Self::reply_error(msg, CreateResponse::EditInteraction(EditInteractionResponse::new()));
+
+        if let Err(e) = io.edit_response(&command.token, edit.into_edit_interaction()).await {
+            tracing::error!("unable to send error: {}", e);
+        }
+    }
+
+    /// Replaces the deferred response to `command` with an error only its author can see,
+    /// as a deferred response cannot become ephemeral.
+    async fn followup_ephemeral_error(io: &dyn DiscordIo, command: &CommandInteraction, msg: &str) {
+        if let Err(e) = io.delete_response(&command.token).await {
+            tracing::error!("unable to delete deferred response: {}", e);
+        }
+
+        let followup = CreateInteractionResponseFollowup::new()
+            .ephemeral(true)
+            .embed(Self::error_embed(msg));
+
+        if let Err(e) = io.followup(&command.token, followup).await {
+            tracing::error!("unable to send error: {}", e);
+        }
+    }
+
+    /// Embeds `post` together with the buttons it needs.
+    fn create_embed(post: &dyn Post, context: &EmbedContext, response: CreateResponse) -> CreateResponse {
+        let response = post.create_embed(&context.author, &context.opts, response);
+        let mut rows = Vec::new();
+
+        if post.pager().is_some() && !post.is_hidden(&context.opts) {
+            rows.push(pager_buttons());
+        }
+
+        let mut buttons = Vec::new();
+
+        if context.can_reveal(post) {
+            buttons.push(CreateButton::new(REVEAL_ID).label("Reveal"));
+        }
+
+        buttons.push(
+            CreateButton::new(EDIT_COMMENT_ID)
+                .label("Edit comment")
+                .style(ButtonStyle::Secondary),
+        );
+
+        if context.undoable {
+            buttons.push(CreateButton::new(UNDO_ID).label("✖ Undo").style(ButtonStyle::Secondary));
+        }
+
+        rows.push(CreateActionRow::Buttons(buttons));
+        response.components(rows)
+    }
 
-            let (url, comment) = match &content[..] {
-                [] => (None, None),
-                [a] => (Url::parse(a).ok(), None),
-                args => {
-                    let (urls, comments): (Vec<_>, Vec<_>) = args
-                        .iter()
-                        .filter(|s| !s.is_empty())
-                        .partition(|a| Url::parse(a).is_ok());
+    /// Remembers what is needed to handle the buttons of the embed of `post` in `message`.
+    fn track_embed(&self, post: &Arc<dyn Post>, message: MessageId, context: &EmbedContext) {
+        let entry = TrackedEmbed { post: post.clone(), context: context.clone() };
+        self.embeds.insert(message, entry);
 
-                    let mut urls = urls.into_iter().map(|u| Url::parse(u).unwrap());
+        if let (Some(pager), false) = (post.pager(), post.is_hidden(&context.opts)) {
+            self.pagers.insert(message, pager.state(context.author.id));
+        }
+    }
+
+    /// Sets up everything that comes with the embed of `post` in `sent`.
+    async fn embed_sent(
+        &self,
+        io: &dyn DiscordIo,
+        guild: Option<GuildId>,
+        sent: &Message,
+        post: &Arc<dyn Post>,
+        context: &EmbedContext,
+    ) {
+        self.track_embed(post, sent.id, context);
+        self.remember_embed(guild, sent, post.as_ref());
+        self.add_reactions(io, guild, sent, post.as_ref(), context.module).await;
+        self.start_thread(io, guild, sent.channel_id, sent.id, post.as_ref())
+            .await;
+    }
+
+    /// Remembers that `post` was embedded in `sent` if the guild recognizes duplicates.
+    fn remember_embed(&self, guild: Option<GuildId>, sent: &Message, post: &dyn Post) {
+        if let (Some(guild), Some(_)) = (guild, &self.guild_settings(guild).duplicates) {
+            let seen = SeenPost { channel: sent.channel_id, message: sent.id, posted_at: SystemTime::now() };
+            self.seen_posts.insert((guild, post.canonical_url().to_string()), seen);
+        }
+    }
+
+    /// Finds a recent embed of `post` in `guild`, returning a link to it.
+    fn earlier_embed(&self, guild: Option<GuildId>, post: &dyn Post) -> Option<(String, &DuplicateSettings)> {
+        let guild = guild?;
+        let settings = self.guild_settings(Some(guild)).duplicates.as_ref()?;
+        let seen = self.seen_posts.get(&(guild, post.canonical_url().to_string()))?;
 
-                    let comments: String = Itertools::intersperse(comments.into_iter(), "\n").collect();
+        let age = seen.posted_at.elapsed().unwrap_or_default();
 
-                    (urls.next(), Some(comments))
+        (age < Duration::from_secs(settings.window_secs))
+            .then(|| (seen.message.link(seen.channel, Some(guild)), settings))
+    }
+
+    async fn mark_duplicate(io: &dyn DiscordIo, sent: &Message, settings: &DuplicateSettings) {
+        match ReactionType::try_from(settings.reaction.as_str()) {
+            Ok(reaction) => {
+                if let Err(e) = io.react(sent.channel_id, sent.id, reaction).await {
+                    tracing::error!("unable to mark {} as duplicate: {}", sent.id, e);
+                }
+            },
+            Err(e) => tracing::warn!("invalid reaction '{}': {}", settings.reaction, e),
+        }
+    }
+
+    /// Adds the reactions configured for the channel of `sent` to it.
+    async fn add_reactions(
+        &self,
+        io: &dyn DiscordIo,
+        guild: Option<GuildId>,
+        sent: &Message,
+        post: &dyn Post,
+        module: &str,
+    ) {
+        let Some(settings) = self.guild_settings(guild).reactions.get(&sent.channel_id) else {
+            return;
+        };
+
+        for reaction in settings.reactions(module, post.content_type()) {
+            let reaction = match ReactionType::try_from(reaction.as_str()) {
+                Ok(reaction) => reaction,
+                Err(e) => {
+                    tracing::warn!("invalid reaction '{}': {}", reaction, e);
+                    continue;
                 },
             };
 
-            if let Some(url) = url {
-                match self.get_post(url.clone()).await {
-                    Ok(post) => {
-                        msg.channel_id
-                            .send_message(
-                                &ctx,
-                                post.create_embed(
-                                    &msg.author,
-                                    &EmbedOptions { comment, ..Default::default() },
-                                    CreateResponse::Message(CreateMessage::new()),
-                                )
-                                .into_message(),
-                            )
-                            .await
-                            .unwrap();
-
-                        msg.delete(&ctx).await.unwrap();
-                    },
-                    Err(Error::NoApiAvailable) => {
-                        tracing::info!("not embedding {}: no api available", url);
-                    },
-                    Err(Error::NotSupposedToEmbed(_)) => {
-                        tracing::info!("ignoring {}: not supposed to embed", url);
+            if let Err(e) = io.react(sent.channel_id, sent.id, reaction).await {
+                tracing::error!("unable to react to {}: {}", sent.id, e);
+            }
+        }
+    }
+
+    /// Starts a thread on the embed `message` of `post`, if `channel` has threads set up.
+    async fn start_thread(
+        &self,
+        io: &dyn DiscordIo,
+        guild: Option<GuildId>,
+        channel: ChannelId,
+        message: MessageId,
+        post: &dyn Post,
+    ) {
+        let Some(settings) = self.guild_settings(guild).threads.get(&channel) else {
+            return;
+        };
+
+        let title = post
+            .title()
+            .map(|title| strip_markdown(&title))
+            .unwrap_or_else(|| "Discussion".to_owned());
+
+        let name: String = title
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .chars()
+            .take(THREAD_NAME_MAX_LEN)
+            .collect();
+
+        let thread = CreateThread::new(name).auto_archive_duration(settings.auto_archive_duration);
+
+        if let Err(e) = io.start_thread(channel, message, thread).await {
+            tracing::error!("unable to start thread on {}: {}", message, e);
+        }
+    }
+
+    async fn reveal(&self, io: &dyn DiscordIo, component: &ComponentInteraction) {
+        let response = CreateResponse::Interaction(CreateInteractionResponseMessage::new().ephemeral(true));
+
+        let response = match self.embeds.get(&component.message.id) {
+            Some(TrackedEmbed { post, context }) => post.create_embed(&context.author, &context.reveal_opts, response),
+            None => Self::reply_error("This post can no longer be revealed", response),
+        };
+
+        let response = CreateInteractionResponse::Message(response.into_interaction());
+
+        if let Err(e) = io.respond(component.id, &component.token, response).await {
+            tracing::error!("unable to reveal post: {}", e);
+        }
+    }
+
+    /// Posts `original` in `channel` again, under the name and avatar of its author.
+    async fn repost(io: &dyn DiscordIo, channel: ChannelId, original: &Original) -> anyhow::Result<()> {
+        let message = ExecuteWebhook::new()
+            .content(&original.content)
+            .username(&original.username)
+            .avatar_url(&original.avatar_url)
+            .allowed_mentions(CreateAllowedMentions::new());
+
+        io.repost(channel, message).await
+    }
+
+    /// Brings back the message replaced by the embed of `context`, or its link previews if it was kept.
+    async fn restore(io: &dyn DiscordIo, context: &EmbedContext) -> anyhow::Result<()> {
+        if let Some(original) = &context.original {
+            Self::repost(io, context.source_channel, original).await?;
+        }
+
+        if let Some(Kept { message, suppressed: true }) = context.kept {
+            io.edit_message(
+                context.source_channel,
+                message,
+                EditMessage::new().suppress_embeds(false),
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn undo(&self, io: &dyn DiscordIo, component: &ComponentInteraction) {
+        let id = component.message.id;
+        let mut undone = None;
+
+        let error = match self.embeds.get(&id) {
+            Some(TrackedEmbed { context, .. }) if context.author.id != component.user.id => {
+                Some("Only the person who posted this can undo it")
+            },
+            // taken out before restoring anything, so that clicking twice does not repost the message twice
+            Some(_) => match self.embeds.remove(&id) {
+                Some(tracked) => match Self::restore(io, &tracked.context).await {
+                    Ok(()) => {
+                        undone = Some(tracked.context);
+                        None
                     },
                     Err(e) => {
-                        tracing::error!("error while trying to embed {}: {}", url, e);
+                        tracing::error!("unable to restore original message: {:?}", e);
+                        self.embeds.insert(id, tracked);
+                        Some("Unable to restore your message")
                     },
-                }
+                },
+                None => Some("This embed can no longer be undone"),
+            },
+            None => Some("This embed can no longer be undone"),
+        };
+
+        let response = match error {
+            Some(msg) => CreateInteractionResponse::Message(
+                Self::reply_error(
+                    msg,
+                    CreateResponse::Interaction(CreateInteractionResponseMessage::new().ephemeral(true)),
+                )
+                .into_interaction(),
+            ),
+            None => CreateInteractionResponse::Acknowledge,
+        };
+
+        if let Err(e) = io.respond(component.id, &component.token, response).await {
+            tracing::error!("unable to respond to undo: {}", e);
+        }
+
+        let Some(context) = undone else {
+            return;
+        };
+
+        // the kept message no longer has an embed whose edits and deletion would need following
+        if let Some(kept) = context.kept {
+            self.replies.remove(&kept.message);
+        }
+
+        if let Some(pointer) = context.pointer {
+            if let Err(e) = io.delete_message(context.source_channel, pointer).await {
+                tracing::error!("unable to delete pointer to embed: {}", e);
             }
         }
+
+        if let Err(e) = io.delete_message(component.channel_id, id).await {
+            tracing::error!("unable to delete embed: {}", e);
+        }
     }
 
-    async fn ready(&self, ctx: Context, _ready: Ready) {
-        let _ = Command::create_global_command(
-            &ctx,
-            CreateCommand::new("embed")
-                .kind(CommandType::ChatInput)
-                .description("embed a post")
-                .add_option(
-                    CreateCommandOption::new(CommandOptionType::String, "url", "url of the post").required(true),
-                )
-                .add_option(
-                    CreateCommandOption::new(
-                        CommandOptionType::Boolean,
-                        "ignore-nsfw",
-                        "embed fully even if post is flagged as nsfw",
-                    )
-                    .required(false),
-                )
-                .add_option(
-                    CreateCommandOption::new(
-                        CommandOptionType::Boolean,
-                        "ignore-spoiler",
-                        "embed fully even if post is flagged as spoiler",
-                    )
-                    .required(false),
-                )
-                .add_option(
-                    CreateCommandOption::new(CommandOptionType::String, "comment", "a personal comment to include")
-                        .required(false),
-                ),
-        )
-        .await
-        .unwrap();
+    /// Deletes the embed of the message `source` in `source_channel`, along with the pointer to it
+    /// and the reaction pointing out a failure.
+    #[cfg(feature = "implicit-auto-embed")]
+    async fn delete_reply(&self, io: &dyn DiscordIo, source_channel: ChannelId, source: MessageId, reply: &Reply) {
+        if let Some(message) = reply.message {
+            self.embeds.remove(&message);
+            self.failures.remove(&message);
 
-        tracing::info!("logged in");
-    }
+            if let Err(e) = io.delete_message(reply.channel, message).await {
+                tracing::warn!("unable to delete embed {}: {}", message, e);
+            }
+        }
 
-    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
-        if let Interaction::Command(command) = &interaction {
-            match &command.data {
-                CommandData { name, options, .. } if name == "embed" => {
-                    let url = options
-                        .iter()
-                        .find(|c| c.name == "url")
-                        .unwrap()
-                        .value
-                        .as_str()
-                        .unwrap();
-
-                    let comment = options
-                        .iter()
-                        .find(|c| c.name == "comment")
-                        .and_then(|c| c.value.as_str())
-                        .map(|c| c.to_owned());
-
-                    let ignore_nsfw = options
-                        .iter()
-                        .find(|c| c.name == "ignore-nsfw")
-                        .and_then(|c| c.value.as_bool())
-                        .unwrap_or(false);
-
-                    let ignore_spoiler = options
-                        .iter()
-                        .find(|c| c.name == "ignore-spoiler")
-                        .and_then(|c| c.value.as_bool())
-                        .unwrap_or(false);
-
-                    let opts = EmbedOptions { comment, ignore_nsfw, ignore_spoiler };
-
-                    match Url::parse(url) {
-                        Ok(url) => {
-                            let user = &command.user;
-
-                            match self.get_post(url.clone()).await {
-                                Ok(post) => {
-                                    command
-                                        .create_response(&ctx, CreateInteractionResponse::Message({
-                                            post.create_embed(user, &opts, CreateResponse::Interaction(CreateInteractionResponseMessage::new())).into_interaction()
-                                        }))
-                                        .await
-                                        .unwrap();
-
-                                    tracing::trace!("embedded '{}': {:?}", url, post);
-                                },
-                                Err(e) => {
-                                    let msg = format!("{}", e);
-                                    tracing::error!("error: {msg}");
-
-                                    command
-                                        .create_response(&ctx, CreateInteractionResponse::Message({
-                                            Self::reply_error(&msg, CreateResponse::Interaction(CreateInteractionResponseMessage::new()))
-                                                .into_interaction()
-                                        }))
-                                        .await
-                                        .unwrap();
-                                },
-                            }
-                        },
-                        Err(_) => {
-                            command
-                                .create_response(
-                                    &ctx,
-                                    CreateInteractionResponse::Message({
-                                        Self::reply_error(
-                                            &format!("Could not parse url: {}", url),
-                                            CreateResponse::Interaction(CreateInteractionResponseMessage::new()),
-                                        )
-                                        .into_interaction()
-                                    }),
-                                )
-                                .await
-                                .unwrap();
-                        },
-                    }
-                },
-                _ => (),
+        if let Some(pointer) = reply.pointer {
+            if let Err(e) = io.delete_message(source_channel, pointer).await {
+                tracing::warn!("unable to delete pointer {}: {}", pointer, e);
             }
         }
+
+        if let Some(reaction) = &reply.reaction {
+            if let Err(e) = io.unreact(source_channel, source, reaction.clone()).await {
+                tracing::warn!("unable to remove reaction from {}: {}", source, e);
+            }
+        }
+    }
+
+    /// The guild permissions of the author of `msg`, remembered for a while since looking them up takes a request.
+    #[cfg(feature = "implicit-auto-embed")]
+    async fn author_permissions(&self, io: &dyn DiscordIo, msg: &Message) -> Permissions {
+        let Some(guild) = msg.guild_id else {
+            return Permissions::empty();
+        };
+
+        if let Some(permissions) = self.permissions.get(&(guild, msg.author.id)) {
+            return permissions;
+        }
+
+        match io.author_permissions(msg).await {
+            Ok(permissions) => {
+                self.permissions.insert((guild, msg.author.id), permissions);
+                permissions
+            },
+            Err(e) => {
+                tracing::warn!("unable to look up guild {}, assuming no permissions: {}", guild, e);
+                Permissions::empty()
+            },
+        }
+    }
+
+    /// Works out what to embed for `msg`, logging why if it is nothing.
+    #[cfg(feature = "implicit-auto-embed")]
+    async fn prepare_embed(&self, io: &dyn DiscordIo, msg: &Message) -> Embedding {
+        let (Some(url), comment) = parse_message_content(&msg.content) else {
+            return Embedding::Nothing;
+        };
+
+        let restrictions = &self.guild_settings(msg.guild_id).restrictions;
+
+        let member = match &msg.member {
+            Some(member) if restrictions.needs_message_permissions(&member.roles) => {
+                Some(MemberAccess { roles: &member.roles, permissions: self.author_permissions(io, msg).await })
+            },
+            Some(member) => Some(MemberAccess { roles: &member.roles, permissions: Permissions::empty() }),
+            None => None,
+        };
+
+        if !restrictions.allows_auto_embed(member.as_ref()) {
+            tracing::info!("not embedding {}: {} may not use auto-embed", url, msg.author.name);
+            return Embedding::Nothing;
+        }
+
+        let scope = Scope { user: msg.author.id, channel: msg.channel_id, guild: msg.guild_id };
+
+        let (post, module) = match self.get_post(url.clone(), restrictions, member.as_ref(), &scope).await {
+            Ok(embedded) => embedded,
+            Err(Error::NoApiAvailable) => {
+                tracing::info!("not embedding {}: no api available", url);
+                return Embedding::Nothing;
+            },
+            Err(Error::ModuleNotAllowed(module)) => {
+                tracing::info!("not embedding {}: {} may not use {}", url, msg.author.name, module);
+                return Embedding::Nothing;
+            },
+            Err(Error::RateLimited(wait)) => {
+                tracing::info!("not embedding {}: rate limited for {:?}", url, wait);
+                return Embedding::Postponed;
+            },
+            Err(Error::ModuleUnavailable(module)) => {
+                tracing::debug!("not embedding {}: {} is unavailable", url, module);
+                return Embedding::Postponed;
+            },
+            Err(Error::QueueFull(module)) => {
+                tracing::warn!("not embedding {}: queue of {} is full", url, module);
+                return Embedding::Postponed;
+            },
+            Err(Error::NotSupposedToEmbed(_)) => {
+                tracing::info!("ignoring {}: not supposed to embed", url);
+                return Embedding::Nothing;
+            },
+            Err(e) => {
+                tracing::error!("error while trying to embed {}: {}", url, e);
+                return Embedding::Failed(e);
+            },
+        };
+
+        let opts = EmbedOptions { comment, ..Default::default() };
+
+        let Some((opts, reveal_opts)) = self.apply_nsfw_policy(io, msg.guild_id, msg.channel_id, opts).await else {
+            return Embedding::Nothing;
+        };
+        let opts = self.blur_hidden(module, post.as_ref(), opts).await;
+
+        let username = msg
+            .member
+            .as_ref()
+            .and_then(|member| member.nick.clone())
+            .unwrap_or_else(|| msg.author.display_name().to_owned());
+
+        let context = EmbedContext {
+            author: msg.author.clone(),
+            module,
+            source_channel: msg.channel_id,
+            pointer: None,
+            opts,
+            reveal_opts,
+            undoable: true,
+            original: Some(Original { content: msg.content.clone(), username, avatar_url: msg.author.face() }),
+            kept: None,
+        };
+
+        Embedding::Post(post, Box::new(context))
+    }
+
+    /// Points out that the link in `msg` could not be embedded, if the guild wants that.
+    #[cfg(feature = "implicit-auto-embed")]
+    async fn failure_feedback(&self, io: &dyn DiscordIo, msg: &Message, error: &Error) {
+        let Some(feedback) = &self.guild_settings(msg.guild_id).failure_feedback else {
+            return;
+        };
+
+        let reaction = match ReactionType::try_from(feedback.reaction.as_str()) {
+            Ok(reaction) => match io.react(msg.channel_id, msg.id, reaction.clone()).await {
+                Ok(()) => Some(reaction),
+                Err(e) => {
+                    tracing::error!("unable to react to {}: {}", msg.id, e);
+                    None
+                },
+            },
+            Err(e) => {
+                tracing::warn!("invalid reaction '{}': {}", feedback.reaction, e);
+                None
+            },
+        };
+
+        let note = if feedback.explain {
+            self.explain_failure_note(io, msg, error).await
+        } else {
+            None
+        };
+
+        // tracked even without a note, so that editing the message tries again
+        let reply = Reply {
+            channel: msg.channel_id,
+            message: note,
+            pointer: None,
+            content: msg.content.clone(),
+            suppressed: false,
+            failed: true,
+            reaction,
+        };
+
+        self.replies.insert(msg.id, reply);
+    }
+
+    /// Replies to `msg` with a button explaining why it could not be embedded, returning the reply.
+    #[cfg(feature = "implicit-auto-embed")]
+    async fn explain_failure_note(&self, io: &dyn DiscordIo, msg: &Message, error: &Error) -> Option<MessageId> {
+        let reply = CreateMessage::new()
+            .reference_message(msg)
+            .allowed_mentions(CreateAllowedMentions::new())
+            .content("This link could not be embedded")
+            .components(vec![CreateActionRow::Buttons(vec![CreateButton::new(
+                EXPLAIN_FAILURE_ID,
+            )
+            .label("Why?")
+            .style(ButtonStyle::Secondary)])]);
+
+        match io.send_message(msg.channel_id, reply).await {
+            Ok(sent) => {
+                self.failures
+                    .insert(sent.id, error.scrape_error().unwrap_or(ScrapeError::Other));
+                Some(sent.id)
+            },
+            Err(e) => {
+                tracing::error!("unable to explain failure to embed {}: {}", msg.id, e);
+                None
+            },
+        }
+    }
+
+    async fn explain_failure(&self, io: &dyn DiscordIo, component: &ComponentInteraction) {
+        let response = CreateResponse::Interaction(CreateInteractionResponseMessage::new().ephemeral(true));
+
+        let response = match self.failures.get(&component.message.id) {
+            Some(kind) => response.content(kind.user_message(&component.locale)),
+            None => Self::reply_error("The reason is no longer known", response),
+        };
+
+        let response = CreateInteractionResponse::Message(response.into_interaction());
+
+        if let Err(e) = io.respond(component.id, &component.token, response).await {
+            tracing::error!("unable to explain failure: {}", e);
+        }
+    }
+
+    async fn show_status(&self, io: &dyn DiscordIo, command: &CommandInteraction) {
+        let report = self.health.report(self.apis.iter().map(|api| api.name()));
+
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new().ephemeral(true).embed(
+                CreateEmbed::new()
+                    .title("Module status")
+                    .description(limit_descr_len(&report)),
+            ),
+        );
+
+        if let Err(e) = io.respond(command.id, &command.token, response).await {
+            tracing::error!("unable to send status: {}", e);
+        }
+    }
+
+    async fn purge_cache(&self, io: &dyn DiscordIo, command: &CommandInteraction) {
+        let Some(cache) = &self.post_cache else {
+            return Self::reply_ephemeral_error(io, command, "The post cache is disabled").await;
+        };
+
+        let url = command
+            .data
+            .options
+            .iter()
+            .find(|c| c.name == "url")
+            .and_then(|c| c.value.as_str());
+
+        let purged = match url.map(Url::parse).transpose() {
+            Ok(mut url) => {
+                if let Some(url) = &mut url {
+                    url.set_fragment(None);
+                }
+
+                cache.purge(url.as_ref()).await
+            },
+            Err(_) => return Self::reply_ephemeral_error(io, command, "Could not parse url").await,
+        };
+
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .ephemeral(true)
+                .content(format!("Forgot {purged} cached post(s)")),
+        );
+
+        if let Err(e) = io.respond(command.id, &command.token, response).await {
+            tracing::error!("unable to respond to cache purge: {}", e);
+        }
+    }
+
+    async fn edit_comment(&self, io: &dyn DiscordIo, component: &ComponentInteraction) {
+        let error = |msg| {
+            CreateInteractionResponse::Message(
+                Self::reply_error(
+                    msg,
+                    CreateResponse::Interaction(CreateInteractionResponseMessage::new().ephemeral(true)),
+                )
+                .into_interaction(),
+            )
+        };
+
+        let response = match self.embeds.get(&component.message.id) {
+            Some(TrackedEmbed { context, .. }) if context.author.id != component.user.id => {
+                error("Only the person who posted this can edit its comment")
+            },
+            Some(TrackedEmbed { context, .. }) => {
+                let input = CreateInputText::new(InputTextStyle::Paragraph, "Comment", COMMENT_INPUT_ID)
+                    .value(context.opts.comment.unwrap_or_default())
+                    .max_length(EMBED_FIELD_MAX_LEN as u16)
+                    .required(false);
+
+                CreateInteractionResponse::Modal(
+                    CreateModal::new(COMMENT_MODAL_ID, "Edit comment")
+                        .components(vec![CreateActionRow::InputText(input)]),
+                )
+            },
+            None => error("The comment of this post can no longer be edited"),
+        };
+
+        if let Err(e) = io.respond(component.id, &component.token, response).await {
+            tracing::error!("unable to open comment editor: {}", e);
+        }
+    }
+
+    async fn submit_comment(&self, io: &dyn DiscordIo, modal: &ModalInteraction) {
+        let comment = modal
+            .data
+            .components
+            .iter()
+            .flat_map(|row| &row.components)
+            .find_map(|c| match c {
+                ActionRowComponent::InputText(input) if input.custom_id == COMMENT_INPUT_ID => input.value.clone(),
+                _ => None,
+            })
+            .filter(|comment| !comment.trim().is_empty());
+
+        let message = modal.message.as_ref().map(|m| m.id);
+        let tracked = message.and_then(|message| self.embeds.get(&message));
+
+        let response = match (message, tracked) {
+            (Some(message), Some(TrackedEmbed { post, mut context })) if context.author.id == modal.user.id => {
+                context.set_comment(comment);
+
+                let response = Self::create_embed(
+                    post.as_ref(),
+                    &context,
+                    CreateResponse::Interaction(CreateInteractionResponseMessage::new()),
+                );
+
+                // re-rendering starts galleries over from the first image
+                self.track_embed(&post, message, &context);
+                CreateInteractionResponse::UpdateMessage(response.into_interaction())
+            },
+            _ => CreateInteractionResponse::Message(
+                Self::reply_error(
+                    "The comment of this post can no longer be edited",
+                    CreateResponse::Interaction(CreateInteractionResponseMessage::new().ephemeral(true)),
+                )
+                .into_interaction(),
+            ),
+        };
+
+        if let Err(e) = io.respond(modal.id, &modal.token, response).await {
+            tracing::error!("unable to update comment: {}", e);
+        }
+    }
+
+    async fn page_gallery(&self, io: &dyn DiscordIo, component: &ComponentInteraction, forward: bool) {
+        let user = component.user.id;
+
+        let state = self.pagers.update(&component.message.id, |state| {
+            state.is_allowed(user).then(|| {
+                state.advance(forward);
+                state.clone()
+            })
+        });
+
+        let response = match state {
+            Some(Some(state)) => {
+                let embed = component
+                    .message
+                    .embeds
+                    .first()
+                    .cloned()
+                    .map(CreateEmbed::from)
+                    .unwrap_or_default();
+
+                CreateInteractionResponse::UpdateMessage(
+                    CreateInteractionResponseMessage::new().embed(state.show(embed)),
+                )
+            },
+            Some(None) => CreateInteractionResponse::Message(
+                Self::reply_error(
+                    "Only the person who posted this gallery can page through it",
+                    CreateResponse::Interaction(CreateInteractionResponseMessage::new().ephemeral(true)),
+                )
+                .into_interaction(),
+            ),
+            None => CreateInteractionResponse::Message(
+                Self::reply_error(
+                    "This gallery can no longer be paged through",
+                    CreateResponse::Interaction(CreateInteractionResponseMessage::new().ephemeral(true)),
+                )
+                .into_interaction(),
+            ),
+        };
+
+        if let Err(e) = io.respond(component.id, &component.token, response).await {
+            tracing::error!("unable to page gallery: {}", e);
+        }
+    }
+
+    #[cfg(feature = "implicit-auto-embed")]
+    async fn handle_message(&self, io: &dyn DiscordIo, msg: &Message) {
+        if msg.author.bot {
+            return;
+        }
+
+        let embedding = self.prepare_embed(io, msg).await;
+        self.embed_message(io, msg, embedding).await;
+    }
+
+    /// Sends the embed of `msg`, or points out that it failed.
+    #[cfg(feature = "implicit-auto-embed")]
+    async fn embed_message(&self, io: &dyn DiscordIo, msg: &Message, embedding: Embedding) {
+        let (post, mut context) = match embedding {
+            Embedding::Post(post, context) => (post, context),
+            Embedding::Nothing | Embedding::Postponed => return,
+            Embedding::Failed(e) => return self.failure_feedback(io, msg, &e).await,
+        };
+
+        let duplicate = self.earlier_embed(msg.guild_id, post.as_ref());
+
+        match duplicate {
+            Some((earlier, DuplicateSettings { action: DuplicateAction::Reply, .. })) => {
+                let reply = CreateMessage::new()
+                    .reference_message(msg)
+                    .allowed_mentions(CreateAllowedMentions::new())
+                    .content(format!("This post was shared recently: {}", earlier));
+
+                if let Err(e) = io.send_message(msg.channel_id, reply).await {
+                    tracing::error!("unable to point to earlier embed: {}", e);
+                }
+
+                return;
+            },
+            Some((earlier, DuplicateSettings { action: DuplicateAction::Refuse, .. })) => {
+                tracing::info!("not embedding {}: already embedded in {}", msg.id, earlier);
+                return;
+            },
+            _ => (),
+        }
+
+        let settings = self.guild_settings(msg.guild_id);
+        let mode = settings.original_message;
+        let redirect = settings.redirect_target(msg.channel_id);
+
+        if mode != OriginalMessageMode::Delete {
+            context.original = None;
+        }
+
+        // replies to the original message, with the embed or a pointer to it
+        let reply = || match mode {
+            OriginalMessageMode::Delete => CreateMessage::new(),
+            OriginalMessageMode::SuppressEmbeds | OriginalMessageMode::ReplyOnly => CreateMessage::new()
+                .reference_message(msg)
+                .allowed_mentions(CreateAllowedMentions::new().replied_user(false)),
+        };
+
+        let (channel, message) = match redirect {
+            Some(target) => (target, CreateMessage::new()),
+            None => (msg.channel_id, reply()),
+        };
+
+        let sent = io
+            .send_message(
+                channel,
+                Self::create_embed(post.as_ref(), &context, CreateResponse::Message(message)).into_message(),
+            )
+            .await;
+
+        let sent = match sent {
+            Ok(sent) => sent,
+            Err(e) => {
+                tracing::error!("unable to send embed for {}: {}", msg.id, e);
+                return;
+            },
+        };
+
+        if redirect.is_some() {
+            let pointer = reply()
+                .content(format!(
+                    "{} shared a post: {}",
+                    msg.author.mention(),
+                    sent.id.link(sent.channel_id, msg.guild_id)
+                ))
+                .allowed_mentions(CreateAllowedMentions::new());
+
+            match io.send_message(msg.channel_id, pointer).await {
+                Ok(pointer) => context.pointer = Some(pointer.id),
+                Err(e) => tracing::error!("unable to point to redirected embed {}: {}", sent.id, e),
+            }
+        }
+
+        let kept = match mode {
+            OriginalMessageMode::Delete => match io.delete_message(msg.channel_id, msg.id).await {
+                Ok(()) => false,
+                Err(e) => {
+                    tracing::warn!("unable to delete message {}, keeping it: {}", msg.id, e);
+                    context.original = None;
+                    context.kept = Some(Kept { message: msg.id, suppressed: false });
+                    true
+                },
+            },
+            OriginalMessageMode::SuppressEmbeds => {
+                let suppressed = io
+                    .edit_message(msg.channel_id, msg.id, EditMessage::new().suppress_embeds(true))
+                    .await;
+
+                if let Err(e) = &suppressed {
+                    tracing::warn!("unable to suppress link previews of message {}: {}", msg.id, e);
+                }
+
+                context.kept = Some(Kept { message: msg.id, suppressed: suppressed.is_ok() });
+                true
+            },
+            OriginalMessageMode::ReplyOnly => {
+                context.kept = Some(Kept { message: msg.id, suppressed: false });
+                true
+            },
+        };
+
+        if kept {
+            let reply = Reply {
+                channel: sent.channel_id,
+                message: Some(sent.id),
+                pointer: context.pointer,
+                content: msg.content.clone(),
+                suppressed: context.kept.is_some_and(|kept| kept.suppressed),
+                failed: false,
+                reaction: None,
+            };
+
+            self.replies.insert(msg.id, reply);
+        }
+
+        if let Some((_, settings)) = duplicate {
+            Self::mark_duplicate(io, &sent, settings).await;
+        }
+
+        self.embed_sent(io, msg.guild_id, &sent, &post, &context).await;
+    }
+
+    #[cfg(feature = "implicit-auto-embed")]
+    async fn handle_message_update(&self, io: &dyn DiscordIo, event: MessageUpdateEvent) {
+        let Some(reply) = self.replies.get(&event.id) else {
+            return;
+        };
+
+        // updates that only add link previews or suppress them keep the content as is
+        let Some(content) = event.content.filter(|content| *content != reply.content) else {
+            return;
+        };
+
+        // the post stays the same if only the comment changed, so there is no need to fetch it again
+        // unless it is no longer tracked, then it is fetched like for any other edit
+        let (url, comment) = parse_message_content(&content);
+        let unchanged = !reply.failed && url.is_some() && url == parse_message_content(&reply.content).0;
+
+        if let Some(TrackedEmbed { post, mut context }) = reply
+            .message
+            .filter(|_| unchanged)
+            .and_then(|message| self.embeds.get(&message))
+        {
+            context.set_comment(comment);
+            self.replace_embed(io, event.id, Reply { content, ..reply }, &post, &context)
+                .await;
+            return;
+        }
+
+        let mut msg = match io.message(event.channel_id, event.id).await {
+            Ok(msg) => msg,
+            Err(e) => {
+                tracing::error!("unable to fetch edited message {}: {}", event.id, e);
+                return;
+            },
+        };
+
+        // messages fetched over http lack the guild they were sent in
+        msg.guild_id = event.guild_id;
+        msg.member = event.member.flatten();
+
+        let embedding = self.prepare_embed(io, &msg).await;
+
+        // the edit is another try at embedding the link, which makes the note about the last one obsolete
+        if reply.failed {
+            if !matches!(embedding, Embedding::Postponed) {
+                self.replies.remove(&msg.id);
+                self.delete_reply(io, msg.channel_id, msg.id, &reply).await;
+            }
+
+            return self.embed_message(io, &msg, embedding).await;
+        }
+
+        match embedding {
+            Embedding::Post(post, mut context) => {
+                context.original = None;
+                context.kept = Some(Kept { message: msg.id, suppressed: reply.suppressed });
+                context.pointer = reply.pointer;
+
+                self.replace_embed(
+                    io,
+                    msg.id,
+                    Reply { content: msg.content.clone(), ..reply },
+                    &post,
+                    &context,
+                )
+                .await;
+            },
+            Embedding::Nothing => {
+                self.replies.remove(&msg.id);
+                self.delete_reply(io, msg.channel_id, msg.id, &reply).await;
+            },
+            // keep the outdated embed rather than none at all
+            Embedding::Failed(_) | Embedding::Postponed => (),
+        }
+    }
+
+    /// Replaces the embed sent in `reply` to the message `source` with the embed of `post`.
+    #[cfg(feature = "implicit-auto-embed")]
+    async fn replace_embed(
+        &self,
+        io: &dyn DiscordIo,
+        source: MessageId,
+        reply: Reply,
+        post: &Arc<dyn Post>,
+        context: &EmbedContext,
+    ) {
+        // only embeds are replaced, which always have a message
+        let Some(message) = reply.message else {
+            return;
+        };
+
+        let edit = EditMessage::new()
+            .content("")
+            .embeds(Vec::new())
+            .components(Vec::new())
+            .remove_all_attachments();
+
+        let edited = io
+            .edit_message(
+                reply.channel,
+                message,
+                Self::create_embed(post.as_ref(), context, CreateResponse::Edit(edit)).into_edit(),
+            )
+            .await;
+
+        match edited {
+            Ok(()) => {
+                self.track_embed(post, message, context);
+                self.replies.insert(source, reply);
+            },
+            Err(e) => {
+                tracing::warn!("unable to update embed {}, forgetting it: {}", message, e);
+                self.replies.remove(&source);
+            },
+        }
+    }
+
+    #[cfg(feature = "implicit-auto-embed")]
+    async fn handle_message_delete(&self, io: &dyn DiscordIo, channel: ChannelId, message: MessageId) {
+        if let Some(reply) = self.replies.remove(&message) {
+            self.delete_reply(io, channel, message, &reply).await;
+        }
+    }
+
+    async fn embed_command(&self, io: &dyn DiscordIo, command: &CommandInteraction) {
+        let options = &command.data.options;
+
+        let Some(url) = options.iter().find(|c| c.name == "url").and_then(|c| c.value.as_str()) else {
+            Self::reply_ephemeral_error(io, command, "Missing url").await;
+            return;
+        };
+
+        let comment = options
+            .iter()
+            .find(|c| c.name == "comment")
+            .and_then(|c| c.value.as_str())
+            .map(|c| c.to_owned());
+
+        let ignore_nsfw = options
+            .iter()
+            .find(|c| c.name == "ignore-nsfw")
+            .and_then(|c| c.value.as_bool())
+            .unwrap_or(false);
+
+        let ignore_spoiler = options
+            .iter()
+            .find(|c| c.name == "ignore-spoiler")
+            .and_then(|c| c.value.as_bool())
+            .unwrap_or(false);
+
+        let opts = EmbedOptions { comment, ignore_nsfw, ignore_spoiler, ..Default::default() };
+
+        let restrictions = &self.guild_settings(command.guild_id).restrictions;
+
+        let member = command
+            .member
+            .as_ref()
+            .map(|member| MemberAccess { roles: &member.roles, permissions: member.permissions.unwrap_or_default() });
+
+        // options set to false do not change anything, so they are always allowed
+        let denied_option = options
+            .iter()
+            .find(|c| c.value.as_bool() != Some(false) && !restrictions.allows_option(&c.name, member.as_ref()));
+
+        if let Some(option) = denied_option {
+            let msg = format!("You are not allowed to use the `{}` option", option.name);
+            Self::reply_ephemeral_error(io, command, &msg).await;
+            return;
+        }
+
+        let Ok(url) = Url::parse(url) else {
+            let response = CreateInteractionResponse::Message(
+                Self::reply_error(
+                    &format!("Could not parse url: {}", url),
+                    CreateResponse::Interaction(CreateInteractionResponseMessage::new()),
+                )
+                .into_interaction(),
+            );
+
+            if let Err(e) = io.respond(command.id, &command.token, response).await {
+                tracing::error!("unable to send error: {}", e);
+            }
+
+            return;
+        };
+
+        // fetching the post may take longer than discord waits for a response
+        let defer = CreateInteractionResponse::Defer(CreateInteractionResponseMessage::new());
+        if let Err(e) = io.respond(command.id, &command.token, defer).await {
+            tracing::error!("unable to defer response: {}", e);
+            return;
+        }
+
+        let user = &command.user;
+
+        let Some((opts, reveal_opts)) = self
+            .apply_nsfw_policy(io, command.guild_id, command.channel_id, opts)
+            .await
+        else {
+            let msg = "`ignore-nsfw` is only allowed in age-restricted channels";
+            Self::followup_ephemeral_error(io, command, msg).await;
+            return;
+        };
+
+        let scope = Scope { user: user.id, channel: command.channel_id, guild: command.guild_id };
+
+        match self.get_post(url.clone(), restrictions, member.as_ref(), &scope).await {
+            Ok((post, module)) => {
+                let duplicate = self.earlier_embed(command.guild_id, post.as_ref());
+
+                if let Some((earlier, settings)) = &duplicate {
+                    if settings.action != DuplicateAction::React {
+                        let msg = format!("This post was shared recently: {}", earlier);
+                        Self::followup_ephemeral_error(io, command, &msg).await;
+                        return;
+                    }
+                }
+
+                // there is no message to restore, undoing only removes the embed
+                let opts = self.blur_hidden(module, post.as_ref(), opts).await;
+                let context = EmbedContext {
+                    author: user.clone(),
+                    module,
+                    source_channel: command.channel_id,
+                    pointer: None,
+                    opts,
+                    reveal_opts,
+                    undoable: true,
+                    original: None,
+                    kept: None,
+                };
+
+                let edit = Self::create_embed(
+                    post.as_ref(),
+                    &context,
+                    CreateResponse::EditInteraction(EditInteractionResponse::new()),
+                )
+                .into_edit_interaction();
+
+                match io.edit_response(&command.token, edit).await {
+                    Ok(sent) => {
+                        if let Some((_, settings)) = duplicate {
+                            Self::mark_duplicate(io, &sent, settings).await;
+                        }
+
+                        self.embed_sent(io, command.guild_id, &sent, &post, &context).await
+                    },
+                    Err(e) => {
+                        tracing::error!("unable to send embed: {}", e);
+                        return;
+                    },
+                }
+
+                tracing::trace!("embedded '{}': {:?}", url, post);
+            },
+            Err(
+                e @ (Error::ModuleNotAllowed(_)
+                | Error::RateLimited(_)
+                | Error::QueueFull(_)
+                | Error::ModuleUnavailable(_)),
+            ) => {
+                Self::followup_ephemeral_error(io, command, &e.user_message(&command.locale)).await;
+            },
+            Err(e) => {
+                tracing::error!("error while trying to embed {}: {}", url, e);
+                Self::reply_deferred_error(io, command, &e.user_message(&command.locale)).await;
+            },
+        }
+    }
+
+    async fn handle_interaction(&self, io: &dyn DiscordIo, interaction: &Interaction) {
+        match interaction {
+            Interaction::Command(command) => match command.data.name.as_str() {
+                "embed" => self.embed_command(io, command).await,
+                "purge-cache" => self.purge_cache(io, command).await,
+                "embed-status" => self.show_status(io, command).await,
+                _ => (),
+            },
+            Interaction::Component(component) => match component.data.custom_id.as_str() {
+                PAGER_PREV_ID => self.page_gallery(io, component, false).await,
+                PAGER_NEXT_ID => self.page_gallery(io, component, true).await,
+                REVEAL_ID => self.reveal(io, component).await,
+                UNDO_ID => self.undo(io, component).await,
+                EDIT_COMMENT_ID => self.edit_comment(io, component).await,
+                EXPLAIN_FAILURE_ID => self.explain_failure(io, component).await,
+                _ => (),
+            },
+            Interaction::Modal(modal) if modal.data.custom_id == COMMENT_MODAL_ID => {
+                self.submit_comment(io, modal).await;
+            },
+            _ => (),
+        }
+    }
+}
+
+#[async_trait]
+impl EventHandler for EmbedBot {
+    #[cfg(feature = "implicit-auto-embed")]
+    async fn message(&self, ctx: Context, msg: Message) {
+        self.handle_message(&SerenityIo::new(&ctx), &msg).await;
+    }
+
+    #[cfg(feature = "implicit-auto-embed")]
+    async fn message_update(
+        &self,
+        ctx: Context,
+        _old_if_available: Option<Message>,
+        _new: Option<Message>,
+        event: MessageUpdateEvent,
+    ) {
+        self.handle_message_update(&SerenityIo::new(&ctx), event).await;
+    }
+
+    #[cfg(feature = "implicit-auto-embed")]
+    async fn message_delete(
+        &self,
+        ctx: Context,
+        channel_id: ChannelId,
+        deleted_message_id: MessageId,
+        _guild_id: Option<GuildId>,
+    ) {
+        self.handle_message_delete(&SerenityIo::new(&ctx), channel_id, deleted_message_id)
+            .await;
+    }
+
+    async fn ready(&self, ctx: Context, _ready: Ready) {
+        let mut commands = vec![
+            CreateCommand::new("embed")
+                .kind(CommandType::ChatInput)
+                .description("embed a post")
+                .add_option(
+                    CreateCommandOption::new(CommandOptionType::String, "url", "url of the post").required(true),
+                )
+                .add_option(
+                    CreateCommandOption::new(
+                        CommandOptionType::Boolean,
+                        "ignore-nsfw",
+                        "embed fully even if post is flagged as nsfw",
+                    )
+                    .required(false),
+                )
+                .add_option(
+                    CreateCommandOption::new(
+                        CommandOptionType::Boolean,
+                        "ignore-spoiler",
+                        "embed fully even if post is flagged as spoiler",
+                    )
+                    .required(false),
+                )
+                .add_option(
+                    CreateCommandOption::new(CommandOptionType::String, "comment", "a personal comment to include")
+                        .required(false),
+                ),
+            CreateCommand::new("embed-status")
+                .kind(CommandType::ChatInput)
+                .description("show how well the modules are working")
+                .default_member_permissions(Permissions::ADMINISTRATOR),
+        ];
+
+        if self.post_cache.is_some() {
+            commands.push(
+                CreateCommand::new("purge-cache")
+                    .kind(CommandType::ChatInput)
+                    .description("forget cached posts, so that they are scraped again")
+                    .default_member_permissions(Permissions::ADMINISTRATOR)
+                    .add_option(
+                        CreateCommandOption::new(CommandOptionType::String, "url", "url of the post, all if unset")
+                            .required(false),
+                    ),
+            );
+        }
+
+        for command in commands {
+            if let Err(e) = Command::create_global_command(&ctx, command).await {
+                tracing::error!("unable to register command: {}", e);
+            }
+        }
+
+        tracing::info!("logged in");
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        self.handle_interaction(&SerenityIo::new(&ctx), &interaction).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::post_grab_api::{ContentType, PostScraper};
+    use discord_io::recording::{Call, RecordingIo};
+    use serenity::model::{channel::AutoArchiveDuration, id::RoleId};
+
+    #[derive(Serialize, Deserialize, Debug)]
+    struct TestPost {
+        url: Url,
+    }
+
+    impl Post for TestPost {
+        fn create_embed(&self, _u: &User, opts: &EmbedOptions, response: CreateResponse) -> CreateResponse {
+            let mut e = CreateEmbed::new().title("post").url(self.url.as_str());
+
+            if let Some(comment) = &opts.comment {
+                e = e.description(comment);
+            }
+
+            response.embed(e)
+        }
+
+        fn content_type(&self) -> ContentType {
+            ContentType::Text
+        }
+
+        fn canonical_url(&self) -> Url {
+            self.url.clone()
+        }
+
+        fn title(&self) -> Option<String> {
+            (self.url.path() == "/titled").then(|| format!("A **titled**\n\\*post* {}", "long ".repeat(30)))
+        }
+    }
+
+    /// Embeds example.com links except `/hidden`, `/missing` fails and only `/titled` has a title
+    struct TestScraper;
+
+    #[async_trait]
+    impl PostScraper for TestScraper {
+        type Output = TestPost;
+
+        fn name(&self) -> &'static str {
+            "test"
+        }
+
+        fn is_suitable(&self, url: &Url) -> bool {
+            url.domain() == Some("example.com")
+        }
+
+        fn should_embed(&self, post: &Self::Output) -> bool {
+            post.url.path() != "/hidden"
+        }
+
+        async fn get_post(&self, url: Url) -> anyhow::Result<Self::Output> {
+            if url.path() == "/missing" {
+                anyhow::bail!(ScrapeError::NotFound);
+            }
+
+            Ok(TestPost { url })
+        }
+    }
+
+    fn bot() -> EmbedBot {
+        let mut bot = EmbedBot::new();
+        bot.register_api(TestScraper);
+        bot
+    }
+
+    fn command(options: serde_json::Value) -> CommandInteraction {
+        serde_json::from_value(serde_json::json!({
+            "id": "10",
+            "application_id": "11",
+            "data": { "id": "12", "name": "embed", "type": 1, "options": options },
+            "channel_id": "2",
+            "token": "token",
+            "version": 1,
+            "locale": "en-US",
+            "entitlements": [],
+            "attachment_size_limit": 0,
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn choose_error() {
+        let bot = bot();
+        let restrictions = Restrictions::default();
+        let scope = Scope { user: UserId::new(1), channel: ChannelId::new(2), guild: None };
+
+        let get_post = |url: &str| bot.get_post(Url::parse(url).unwrap(), &restrictions, None, &scope);
+
+        assert!(matches!(
+            get_post("https://example.org/post").await,
+            Err(Error::NoApiAvailable)
+        ));
+        assert!(matches!(
+            get_post("https://example.com/hidden").await,
+            Err(Error::NotSupposedToEmbed(_))
+        ));
+        assert!(matches!(get_post("https://example.com/post").await, Ok((_, "test"))));
+    }
+
+    #[tokio::test]
+    async fn rate_limit_probe() {
+        let mut bot = bot();
+        bot.enable_content_type_probe(ContentTypeProbeSettings { cache_size: 16, cache_ttl_secs: 60 });
+
+        let bucket = rate_limit::BucketSettings { capacity: 1, refill_secs: 3600.0 };
+        let limits = rate_limit::RateLimits { user: Some(bucket), ..Default::default() };
+        bot.enable_rate_limits(RateLimitSettings { default: limits, modules: HashMap::new() });
+
+        let restrictions = Restrictions::default();
+        let scope = Scope { user: UserId::new(1), channel: ChannelId::new(2), guild: None };
+        let get_post = |url: &str| bot.get_post(Url::parse(url).unwrap(), &restrictions, None, &scope);
+
+        // internal hosts are never asked, but the attempt still counts
+        assert!(matches!(
+            get_post("http://127.0.0.1/a").await,
+            Err(Error::NoApiAvailable)
+        ));
+        assert!(matches!(
+            get_post("http://127.0.0.1/b").await,
+            Err(Error::RateLimited(_))
+        ));
+
+        // known results are free
+        assert!(matches!(
+            get_post("http://127.0.0.1/a").await,
+            Err(Error::NoApiAvailable)
+        ));
+        assert!(matches!(
+            get_post("ftp://example.org/a").await,
+            Err(Error::NoApiAvailable)
+        ));
+    }
+
+    #[tokio::test]
+    async fn embed_command() {
+        let bot = bot();
+        let io = RecordingIo::new();
+
+        let options = serde_json::json!([
+            { "name": "url", "type": 3, "value": "https://example.com/post" },
+            { "name": "comment", "type": 3, "value": "look at this" },
+        ]);
+        bot.embed_command(&io, &command(options)).await;
+
+        match &io.calls()[..] {
+            [Call::Respond { response: defer, .. }, Call::EditResponse { edit }] => {
+                assert_eq!(defer["type"], 5);
+
+                let embed = &edit["embeds"][0];
+                assert_eq!(embed["url"], "https://example.com/post");
+                assert_eq!(embed["description"], "look at this");
+            },
+            calls => panic!("unexpected calls: {calls:?}"),
+        }
+
+        let options = serde_json::json!([{ "name": "url", "type": 3, "value": "https://example.org/post" }]);
+        bot.embed_command(&io, &command(options)).await;
+
+        match &io.calls()[..] {
+            [Call::Respond { .. }, Call::EditResponse { edit }] => {
+                assert_eq!(
+                    edit["embeds"][0]["description"],
+                    "Posts from this site cannot be embedded."
+                );
+            },
+            calls => panic!("unexpected calls: {calls:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn nsfw_policy() {
+        let mut bot = bot();
+        let io = RecordingIo::new();
+        let nsfw_option = serde_json::json!([
+            { "name": "url", "type": 3, "value": "https://example.com/post" },
+            { "name": "ignore-nsfw", "type": 5, "value": true },
+        ]);
+
+        // the default policy leaves it to the user, whatever the channel
+        bot.embed_command(&io, &command(nsfw_option.clone())).await;
+        assert!(matches!(
+            &io.calls()[..],
+            [Call::Respond { .. }, Call::EditResponse { .. }]
+        ));
+
+        let nsfw = guild_settings::NsfwSettings { policy: guild_settings::NsfwPolicy::Strict, allow_in_dms: false };
+        bot.set_guild_settings(GuildSettings { nsfw, ..Default::default() }, HashMap::new());
+        bot.embed_command(&io, &command(nsfw_option)).await;
+
+        match &io.calls()[..] {
+            [Call::Respond { .. }, Call::ChannelRating { channel }, Call::DeleteResponse, Call::Followup { followup }] =>
+            {
+                assert_eq!(*channel, ChannelId::new(2));
+                assert_eq!(followup["flags"], 64);
+            },
+            calls => panic!("unexpected calls: {calls:?}"),
+        }
+    }
+
+    fn component(custom_id: &str, user: u64, message: MessageId) -> ComponentInteraction {
+        let mut msg = Message::default();
+        msg.id = message;
+        msg.channel_id = ChannelId::new(2);
+
+        let mut author = User::default();
+        author.id = UserId::new(user);
+
+        serde_json::from_value(serde_json::json!({
+            "id": "20",
+            "application_id": "11",
+            "type": 3,
+            "data": { "custom_id": custom_id, "component_type": 2 },
+            "channel_id": "2",
+            "user": author,
+            "message": msg,
+            "token": "token",
+            "version": 1,
+            "locale": "en-US",
+            "entitlements": [],
+            "attachment_size_limit": 0,
+        }))
+        .unwrap()
+    }
+
+    fn gallery(owner: Option<u64>) -> PagerState {
+        PagerState {
+            images: vec![
+                Url::parse("https://example.com/1.png").unwrap(),
+                Url::parse("https://example.com/2.png").unwrap(),
+            ],
+            index: 0,
+            owner: owner.map(UserId::new),
+        }
+    }
+
+    #[tokio::test]
+    async fn page_gallery() {
+        let bot = bot();
+        let io = RecordingIo::new();
+        let msg = MessageId::new(5);
+        bot.pagers.insert(msg, gallery(Some(1)));
+
+        bot.page_gallery(&io, &component(PAGER_NEXT_ID, 1, msg), true).await;
+
+        match &io.calls()[..] {
+            [Call::Respond { response, .. }] => {
+                let embed = &response["data"]["embeds"][0];
+                assert_eq!(embed["image"]["url"], "https://example.com/2.png");
+                assert_eq!(embed["footer"]["text"], "2/2 images");
+            },
+            calls => panic!("unexpected calls: {calls:?}"),
+        }
+
+        // only the owner may page
+        bot.page_gallery(&io, &component(PAGER_NEXT_ID, 3, msg), true).await;
+
+        match &io.calls()[..] {
+            [Call::Respond { response, .. }] => assert_eq!(response["data"]["flags"], 64),
+            calls => panic!("unexpected calls: {calls:?}"),
+        }
+        assert_eq!(bot.pagers.get(&msg).unwrap().index, 1);
+
+        // paging wraps around in both directions
+        bot.page_gallery(&io, &component(PAGER_NEXT_ID, 1, msg), true).await;
+        assert_eq!(bot.pagers.get(&msg).unwrap().index, 0);
+        bot.page_gallery(&io, &component(PAGER_PREV_ID, 1, msg), false).await;
+        assert_eq!(bot.pagers.get(&msg).unwrap().index, 1);
+    }
+
+    #[tokio::test]
+    async fn pagers_survive_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let msg = MessageId::new(5);
+
+        {
+            let mut bot = bot();
+            bot.set_state_dir(dir.path()).unwrap();
+            bot.pagers.insert(msg, gallery(None));
+            bot.page_gallery(&RecordingIo::new(), &component(PAGER_NEXT_ID, 3, msg), true)
+                .await;
+        }
+
+        let mut bot = bot();
+        bot.set_state_dir(dir.path()).unwrap();
+
+        let state = bot.pagers.get(&msg).unwrap();
+        assert_eq!((state.index, state.images.len()), (1, 2));
+    }
+
+    #[cfg(feature = "implicit-auto-embed")]
+    fn message(content: &str) -> Message {
+        let mut msg = Message::default();
+        msg.id = MessageId::new(1);
+        msg.channel_id = ChannelId::new(2);
+        msg.content = content.to_owned();
+        msg
+    }
+
+    #[cfg(feature = "implicit-auto-embed")]
+    #[tokio::test]
+    async fn look_up_permissions() {
+        let mut bot = bot();
+        let io = RecordingIo::new();
+
+        let restriction = |roles: &[u64], permissions| guild_settings::Restriction {
+            roles: roles.iter().copied().map(RoleId::new).collect(),
+            permissions,
+        };
+        let restrictions = Restrictions {
+            modules: HashMap::from([("test".to_owned(), restriction(&[7], Some(Permissions::MANAGE_MESSAGES)))]),
+            auto_embed: Some(restriction(&[7, 8], None)),
+            ..Default::default()
+        };
+        bot.set_guild_settings(GuildSettings { restrictions, ..Default::default() }, HashMap::new());
+
+        let member_message = |roles: &[u64]| {
+            let mut msg = message("https://example.com/post");
+            msg.guild_id = Some(GuildId::new(3));
+            msg.author.id = UserId::new(4);
+            msg.member = Some(Box::new(
+                serde_json::from_value(serde_json::json!({
+                    "roles": roles.iter().map(u64::to_string).collect::<Vec<_>>(),
+                    "deaf": false,
+                    "mute": false,
+                    "flags": 0,
+                }))
+                .unwrap(),
+            ));
+            msg
+        };
+
+        // the role is enough, there is no need to ask
+        bot.handle_message(&io, &member_message(&[7])).await;
+        assert!(matches!(&io.calls()[..], [Call::Send { .. }, Call::Delete { .. }]));
+
+        // without it the permissions decide, and are remembered
+        bot.handle_message(&io, &member_message(&[8])).await;
+        assert!(matches!(&io.calls()[..], [Call::AuthorPermissions { .. }]));
+
+        bot.handle_message(&io, &member_message(&[8])).await;
+        assert!(io.calls().is_empty());
+    }
+
+    #[cfg(feature = "implicit-auto-embed")]
+    #[tokio::test]
+    async fn undo_repost() {
+        let bot = bot();
+        let io = RecordingIo::new();
+
+        let mut msg = message("https://example.com/post");
+        msg.author.id = UserId::new(4);
+        bot.handle_message(&io, &msg).await;
+        io.calls();
+
+        // only the author may undo
+        bot.undo(&io, &component(UNDO_ID, 3, MessageId::new(1001))).await;
+        assert!(matches!(&io.calls()[..], [Call::Respond { .. }]));
+
+        bot.undo(&io, &component(UNDO_ID, 4, MessageId::new(1001))).await;
+
+        match &io.calls()[..] {
+            [Call::Repost { message, .. }, Call::Respond { .. }, Call::Delete { message: deleted, .. }] => {
+                assert_eq!(message["content"], "https://example.com/post");
+                assert_eq!(*deleted, MessageId::new(1001));
+            },
+            calls => panic!("unexpected calls: {calls:?}"),
+        }
+
+        // a second click finds nothing left to undo
+        bot.undo(&io, &component(UNDO_ID, 4, MessageId::new(1001))).await;
+
+        match &io.calls()[..] {
+            [Call::Respond { response, .. }] => assert_eq!(response["data"]["flags"], 64),
+            calls => panic!("unexpected calls: {calls:?}"),
+        }
+    }
+
+    #[cfg(feature = "implicit-auto-embed")]
+    #[tokio::test]
+    async fn undo_suppressed() {
+        let mut bot = bot();
+        let io = RecordingIo::new();
+
+        let defaults = GuildSettings { original_message: OriginalMessageMode::SuppressEmbeds, ..Default::default() };
+        bot.set_guild_settings(defaults, HashMap::new());
+
+        let mut msg = message("https://example.com/post");
+        msg.author.id = UserId::new(4);
+        bot.handle_message(&io, &msg).await;
+
+        match &io.calls()[..] {
+            [Call::Send { .. }, Call::Edit { message, edit, .. }] => {
+                assert_eq!(*message, MessageId::new(1));
+                assert_eq!(edit["flags"], 4);
+            },
+            calls => panic!("unexpected calls: {calls:?}"),
+        }
+
+        bot.undo(&io, &component(UNDO_ID, 4, MessageId::new(1001))).await;
+
+        match &io.calls()[..] {
+            [Call::Edit { message, edit, .. }, Call::Respond { .. }, Call::Delete { message: deleted, .. }] => {
+                assert_eq!(*message, MessageId::new(1));
+                assert_eq!(edit["flags"], 0);
+                assert_eq!(*deleted, MessageId::new(1001));
+            },
+            calls => panic!("unexpected calls: {calls:?}"),
+        }
+
+        assert!(bot.replies.get(&MessageId::new(1)).is_none());
+    }
+
+    #[cfg(feature = "implicit-auto-embed")]
+    #[tokio::test]
+    async fn original_message_modes() {
+        let mut bot = bot();
+        let io = RecordingIo::new();
+
+        let modes = [
+            OriginalMessageMode::Delete,
+            OriginalMessageMode::SuppressEmbeds,
+            OriginalMessageMode::ReplyOnly,
+        ];
+
+        for mode in modes {
+            bot.set_guild_settings(
+                GuildSettings { original_message: mode, ..Default::default() },
+                HashMap::new(),
+            );
+            bot.handle_message(&io, &message("https://example.com/post")).await;
+
+            let calls = io.calls();
+            let Some(Call::Send { message, .. }) = calls.first() else {
+                panic!("{mode:?}: unexpected calls: {calls:?}");
+            };
+
+            // kept messages are replied to, without pinging their author
+            let reference = &message["message_reference"];
+            match mode {
+                OriginalMessageMode::Delete => assert!(reference.is_null()),
+                _ => {
+                    assert_eq!(reference["message_id"], "1", "{mode:?}");
+                    assert_eq!(message["allowed_mentions"]["replied_user"], false, "{mode:?}");
+                },
+            }
+
+            match (mode, &calls[1..]) {
+                (OriginalMessageMode::Delete, [Call::Delete { message, .. }]) => {
+                    assert_eq!(*message, MessageId::new(1));
+                },
+                (OriginalMessageMode::SuppressEmbeds, [Call::Edit { message, edit, .. }]) => {
+                    assert_eq!(*message, MessageId::new(1));
+                    assert_eq!(edit["flags"], 4);
+                },
+                (OriginalMessageMode::ReplyOnly, []) => (),
+                (_, calls) => panic!("{mode:?}: unexpected calls: {calls:?}"),
+            }
+
+            // only kept messages are followed
+            let followed = bot.replies.remove(&MessageId::new(1)).is_some();
+            assert_eq!(followed, mode != OriginalMessageMode::Delete, "{mode:?}");
+        }
+    }
+
+    #[cfg(feature = "implicit-auto-embed")]
+    #[tokio::test]
+    async fn start_threads() {
+        let mut bot = bot();
+        let io = RecordingIo::new();
+
+        let settings = guild_settings::ThreadSettings { auto_archive_duration: AutoArchiveDuration::OneHour };
+        let defaults = GuildSettings { threads: HashMap::from([(ChannelId::new(2), settings)]), ..Default::default() };
+        bot.set_guild_settings(defaults, HashMap::new());
+
+        bot.handle_message(&io, &message("https://example.com/post")).await;
+
+        match &io.calls()[..] {
+            [Call::Send { .. }, Call::Delete { .. }, Call::StartThread { channel, message, thread }] => {
+                assert_eq!((*channel, *message), (ChannelId::new(2), MessageId::new(1001)));
+                assert_eq!(thread["name"], "Discussion");
+                assert_eq!(thread["auto_archive_duration"], 60);
+            },
+            calls => panic!("unexpected calls: {calls:?}"),
+        }
+
+        // titles lose their markdown and line breaks and are cut short
+        bot.handle_message(&io, &message("https://example.com/titled")).await;
+
+        match &io.calls()[..] {
+            [.., Call::StartThread { thread, .. }] => {
+                let name = thread["name"].as_str().unwrap();
+                assert!(name.starts_with("A titled *post* long long"), "{name}");
+                assert_eq!(name.chars().count(), THREAD_NAME_MAX_LEN);
+            },
+            calls => panic!("unexpected calls: {calls:?}"),
+        }
+
+        // other channels get no threads
+        let mut elsewhere = message("https://example.com/post");
+        elsewhere.channel_id = ChannelId::new(3);
+        bot.handle_message(&io, &elsewhere).await;
+        assert!(matches!(&io.calls()[..], [Call::Send { .. }, Call::Delete { .. }]));
+    }
+
+    #[cfg(feature = "implicit-auto-embed")]
+    #[tokio::test]
+    async fn redirect() {
+        let mut bot = bot();
+        let io = RecordingIo::new();
+
+        let redirect =
+            guild_settings::Redirect { from: [2, 9].into_iter().map(ChannelId::new).collect(), to: ChannelId::new(9) };
+        let defaults = GuildSettings {
+            original_message: OriginalMessageMode::ReplyOnly,
+            redirects: vec![redirect],
+            ..Default::default()
+        };
+        bot.set_guild_settings(defaults, HashMap::new());
+
+        let mut msg = message("https://example.com/post");
+        msg.guild_id = Some(GuildId::new(3));
+        msg.author.id = UserId::new(4);
+        bot.handle_message(&io, &msg).await;
+
+        match &io.calls()[..] {
+            [Call::Send { channel, message }, Call::Send { channel: pointer_channel, message: pointer }] => {
+                assert_eq!(*channel, ChannelId::new(9));
+                assert!(message["message_reference"].is_null());
+
+                assert_eq!(*pointer_channel, ChannelId::new(2));
+                assert_eq!(
+                    pointer["content"],
+                    "<@4> shared a post: https://discord.com/channels/3/9/1001"
+                );
+                assert_eq!(pointer["message_reference"]["message_id"], "1");
+            },
+            calls => panic!("unexpected calls: {calls:?}"),
+        }
+
+        // links posted in the target itself stay where they are
+        let mut in_target = msg.clone();
+        in_target.id = MessageId::new(5);
+        in_target.channel_id = ChannelId::new(9);
+        bot.handle_message(&io, &in_target).await;
+
+        match &io.calls()[..] {
+            [Call::Send { channel, .. }] => assert_eq!(*channel, ChannelId::new(9)),
+            calls => panic!("unexpected calls: {calls:?}"),
+        }
+
+        // the pointer goes away along with the embed
+        bot.handle_message_delete(&io, ChannelId::new(2), MessageId::new(1))
+            .await;
+
+        match &io.calls()[..] {
+            [Call::Delete { channel, message }, Call::Delete { channel: pointer_channel, message: pointer }] => {
+                assert_eq!((*channel, *message), (ChannelId::new(9), MessageId::new(1001)));
+                assert_eq!((*pointer_channel, *pointer), (ChannelId::new(2), MessageId::new(1002)));
+            },
+            calls => panic!("unexpected calls: {calls:?}"),
+        }
+    }
+
+    #[cfg(feature = "implicit-auto-embed")]
+    #[tokio::test]
+    async fn duplicates() {
+        let shared = |id| {
+            let mut msg = message("https://example.com/post");
+            msg.id = MessageId::new(id);
+            msg.guild_id = Some(GuildId::new(3));
+            msg
+        };
+
+        for (action, window_secs) in [
+            (DuplicateAction::Reply, 60),
+            (DuplicateAction::React, 60),
+            (DuplicateAction::Refuse, 60),
+            (DuplicateAction::Refuse, 0),
+        ] {
+            let mut bot = bot();
+            let io = RecordingIo::new();
+
+            let duplicates = DuplicateSettings { window_secs, action, reaction: "♻️".to_owned() };
+            bot.set_guild_settings(
+                GuildSettings { duplicates: Some(duplicates), ..Default::default() },
+                HashMap::new(),
+            );
+
+            bot.handle_message(&io, &shared(1)).await;
+            io.calls();
+            bot.handle_message(&io, &shared(2)).await;
+
+            match (action, window_secs, &io.calls()[..]) {
+                (_, 0, [Call::Send { .. }, Call::Delete { .. }]) => (),
+                (DuplicateAction::Reply, _, [Call::Send { message, .. }]) => {
+                    assert_eq!(
+                        message["content"],
+                        "This post was shared recently: https://discord.com/channels/3/2/1001"
+                    );
+                    assert_eq!(message["message_reference"]["message_id"], "2");
+                },
+                (
+                    DuplicateAction::React,
+                    _,
+                    [Call::Send { .. }, Call::Delete { .. }, Call::React { message, reaction, .. }],
+                ) => {
+                    assert_eq!(*message, MessageId::new(1002));
+                    assert_eq!(reaction, "♻️");
+                },
+                (DuplicateAction::Refuse, _, []) => (),
+                (_, _, calls) => panic!("{action:?} within {window_secs}s: unexpected calls: {calls:?}"),
+            }
+        }
+    }
+
+    #[cfg(feature = "implicit-auto-embed")]
+    fn kept_bot() -> EmbedBot {
+        let mut bot = bot();
+        let defaults = GuildSettings { original_message: OriginalMessageMode::ReplyOnly, ..Default::default() };
+        bot.set_guild_settings(defaults, HashMap::new());
+        bot
+    }
+
+    #[cfg(feature = "implicit-auto-embed")]
+    fn update(msg: &Message) -> MessageUpdateEvent {
+        serde_json::from_value(serde_json::json!({
+            "id": msg.id,
+            "channel_id": msg.channel_id,
+            "content": msg.content,
+        }))
+        .unwrap()
+    }
+
+    #[cfg(feature = "implicit-auto-embed")]
+    #[tokio::test]
+    async fn follow_edits() {
+        let bot = kept_bot();
+        let io = RecordingIo::new();
+
+        bot.handle_message(&io, &message("https://example.com/post")).await;
+        assert!(matches!(&io.calls()[..], [Call::Send { .. }]));
+
+        // previews being added leave the content as is
+        bot.handle_message_update(&io, update(&message("https://example.com/post")))
+            .await;
+        assert!(io.calls().is_empty());
+
+        let edited = message("https://example.com/other\nsee this");
+        io.store(edited.clone());
+        bot.handle_message_update(&io, update(&edited)).await;
+
+        match &io.calls()[..] {
+            [Call::Edit { message, edit, .. }] => {
+                assert_eq!(*message, MessageId::new(1001));
+                assert_eq!(edit["embeds"][0]["url"], "https://example.com/other");
+                assert_eq!(edit["embeds"][0]["description"], "see this");
+            },
+            calls => panic!("unexpected calls: {calls:?}"),
+        }
+
+        // without a link left, the embed goes away and later edits are ignored
+        let edited = message("never mind");
+        io.store(edited.clone());
+        bot.handle_message_update(&io, update(&edited)).await;
+
+        match &io.calls()[..] {
+            [Call::Delete { message, .. }] => assert_eq!(*message, MessageId::new(1001)),
+            calls => panic!("unexpected calls: {calls:?}"),
+        }
+        assert!(bot.replies.get(&MessageId::new(1)).is_none());
+
+        let edited = message("https://example.com/post");
+        io.store(edited.clone());
+        bot.handle_message_update(&io, update(&edited)).await;
+        assert!(io.calls().is_empty());
+    }
+
+    #[cfg(feature = "implicit-auto-embed")]
+    #[tokio::test]
+    async fn rate_limited_edits() {
+        let mut bot = kept_bot();
+        let bucket = rate_limit::BucketSettings { capacity: 1, refill_secs: 3600.0 };
+        let limits = rate_limit::RateLimits { user: Some(bucket), ..Default::default() };
+        bot.enable_rate_limits(RateLimitSettings { default: limits, modules: HashMap::new() });
+        let io = RecordingIo::new();
+
+        bot.handle_message(&io, &message("https://example.com/post")).await;
+        assert!(matches!(&io.calls()[..], [Call::Send { .. }]));
+
+        // changing only the comment re-renders the tracked embed without fetching the post again
+        let edited = message("https://example.com/post\nsee this");
+        io.store(edited.clone());
+        bot.handle_message_update(&io, update(&edited)).await;
+
+        match &io.calls()[..] {
+            [Call::Edit { message, edit, .. }] => {
+                assert_eq!(*message, MessageId::new(1001));
+                assert_eq!(edit["embeds"][0]["description"], "see this");
+            },
+            calls => panic!("unexpected calls: {calls:?}"),
+        }
+
+        // a new link is out of budget, which keeps the current embed around
+        let edited = message("https://example.com/other");
+        io.store(edited.clone());
+        bot.handle_message_update(&io, update(&edited)).await;
+
+        assert!(io.calls().is_empty());
+        assert!(bot.replies.get(&MessageId::new(1)).is_some());
+    }
+
+    #[cfg(feature = "implicit-auto-embed")]
+    #[tokio::test]
+    async fn comment_edit_after_expiry() {
+        let bot = kept_bot();
+        let io = RecordingIo::new();
+
+        bot.handle_message(&io, &message("https://example.com/post")).await;
+        io.calls();
+        bot.embeds.remove(&MessageId::new(1001));
+
+        let edited = message("https://example.com/post\nsee this");
+        io.store(edited.clone());
+        bot.handle_message_update(&io, update(&edited)).await;
+
+        match &io.calls()[..] {
+            [Call::Edit { message, edit, .. }] => {
+                assert_eq!(*message, MessageId::new(1001));
+                assert_eq!(edit["embeds"][0]["description"], "see this");
+            },
+            calls => panic!("unexpected calls: {calls:?}"),
+        }
+    }
+
+    #[cfg(feature = "implicit-auto-embed")]
+    #[tokio::test]
+    async fn follow_deletion() {
+        let bot = kept_bot();
+        let io = RecordingIo::new();
+
+        bot.handle_message(&io, &message("https://example.com/post")).await;
+        io.calls();
+
+        bot.handle_message_delete(&io, ChannelId::new(2), MessageId::new(1))
+            .await;
+
+        match &io.calls()[..] {
+            [Call::Delete { channel, message }] => {
+                assert_eq!((*channel, *message), (ChannelId::new(2), MessageId::new(1001)));
+            },
+            calls => panic!("unexpected calls: {calls:?}"),
+        }
+
+        // the embed cannot be undone any more either
+        assert!(bot.embeds.get(&MessageId::new(1001)).is_none());
+
+        bot.handle_message_delete(&io, ChannelId::new(2), MessageId::new(1))
+            .await;
+        assert!(io.calls().is_empty());
+    }
+
+    #[cfg(feature = "implicit-auto-embed")]
+    #[test]
+    fn parse_content() {
+        let url = Url::parse("https://example.com/post").unwrap();
+
+        assert_eq!(parse_message_content(""), (None, None));
+        assert_eq!(parse_message_content("hello"), (None, None));
+        assert_eq!(
+            parse_message_content("https://example.com/post"),
+            (Some(url.clone()), None)
+        );
+        assert_eq!(
+            parse_message_content("look at this\n\nhttps://example.com/post\nand this"),
+            (Some(url), Some("look at this\nand this".to_owned()))
+        );
+    }
+
+    #[cfg(feature = "implicit-auto-embed")]
+    #[tokio::test]
+    async fn embed_message() {
+        let bot = bot();
+        let io = RecordingIo::new();
+
+        bot.handle_message(&io, &message("https://example.com/post\nlook at this"))
+            .await;
+
+        match &io.calls()[..] {
+            [Call::Send { channel, message }, Call::Delete { channel: deleted_channel, message: deleted }] => {
+                assert_eq!(*channel, ChannelId::new(2));
+                assert_eq!(message["embeds"][0]["description"], "look at this");
+                assert_eq!((*deleted_channel, *deleted), (ChannelId::new(2), MessageId::new(1)));
+            },
+            calls => panic!("unexpected calls: {calls:?}"),
+        }
+
+        // the message was deleted, so there is nothing to follow
+        assert!(bot.replies.get(&MessageId::new(1)).is_none());
+    }
+
+    #[cfg(feature = "implicit-auto-embed")]
+    #[tokio::test]
+    async fn ignore_message() {
+        let bot = bot();
+        let io = RecordingIo::new();
+
+        for content in ["hello", "https://example.org/post", "https://example.com/hidden"] {
+            bot.handle_message(&io, &message(content)).await;
+        }
+
+        let mut from_bot = message("https://example.com/post");
+        from_bot.author.bot = true;
+        bot.handle_message(&io, &from_bot).await;
+
+        assert!(io.calls().is_empty());
+    }
+
+    #[cfg(feature = "implicit-auto-embed")]
+    #[tokio::test]
+    async fn react_to_failure() {
+        let mut bot = bot();
+        let io = RecordingIo::new();
+
+        let feedback = guild_settings::FailureFeedback { reaction: "❌".to_owned(), explain: false };
+        let defaults = GuildSettings { failure_feedback: Some(feedback), ..Default::default() };
+        bot.set_guild_settings(defaults, HashMap::new());
+
+        bot.handle_message(&io, &message("https://example.com/missing")).await;
+
+        match &io.calls()[..] {
+            [Call::React { message, reaction, .. }] => {
+                assert_eq!(*message, MessageId::new(1));
+                assert_eq!(reaction, "❌");
+            },
+            calls => panic!("unexpected calls: {calls:?}"),
+        }
+    }
+
+    #[cfg(feature = "implicit-auto-embed")]
+    #[tokio::test]
+    async fn retry_failure() {
+        let mut bot = bot();
+        let io = RecordingIo::new();
+
+        let feedback = guild_settings::FailureFeedback { reaction: "❌".to_owned(), explain: true };
+        let defaults = GuildSettings {
+            original_message: OriginalMessageMode::ReplyOnly,
+            failure_feedback: Some(feedback),
+            ..Default::default()
+        };
+        bot.set_guild_settings(defaults, HashMap::new());
+
+        bot.handle_message(&io, &message("https://example.com/missing")).await;
+
+        match &io.calls()[..] {
+            [Call::React { .. }, Call::Send { message, .. }] => {
+                assert_eq!(message["content"], "This link could not be embedded");
+            },
+            calls => panic!("unexpected calls: {calls:?}"),
+        }
+
+        // editing the message tries again, replacing the note about the failure
+        let edited = message("https://example.com/missing\nplease");
+        io.store(edited.clone());
+        bot.handle_message_update(&io, update(&edited)).await;
+
+        match &io.calls()[..] {
+            [Call::Delete { message, .. }, Call::Unreact { message: source, reaction, .. }, Call::React { .. }, Call::Send { .. }] =>
+            {
+                assert_eq!(*message, MessageId::new(1001));
+                assert_eq!(*source, MessageId::new(1));
+                assert_eq!(reaction, "❌");
+            },
+            calls => panic!("unexpected calls: {calls:?}"),
+        }
+        assert!(bot.failures.get(&MessageId::new(1001)).is_none());
+
+        let edited = message("https://example.com/post");
+        io.store(edited.clone());
+        bot.handle_message_update(&io, update(&edited)).await;
+
+        match &io.calls()[..] {
+            [Call::Delete { message, .. }, Call::Unreact { .. }, Call::Send { message: embed, .. }] => {
+                assert_eq!(*message, MessageId::new(1002));
+                assert_eq!(embed["embeds"][0]["url"], "https://example.com/post");
+            },
+            calls => panic!("unexpected calls: {calls:?}"),
+        }
+
+        // from here on the embed follows the message like any other
+        assert!(!bot.replies.get(&MessageId::new(1)).unwrap().failed);
+
+        // deleting a message takes the note about its failure with it
+        let mut other = message("https://example.com/missing");
+        other.id = MessageId::new(2);
+        bot.handle_message(&io, &other).await;
+        io.calls();
+
+        bot.handle_message_delete(&io, ChannelId::new(2), MessageId::new(2))
+            .await;
+
+        match &io.calls()[..] {
+            [Call::Delete { message, .. }, Call::Unreact { message: source, .. }] => {
+                assert_eq!(*message, MessageId::new(1004));
+                assert_eq!(*source, MessageId::new(2));
+            },
+            calls => panic!("unexpected calls: {calls:?}"),
+        }
+
+        // without a note the failure is still tracked, so that edits try again
+        let feedback = guild_settings::FailureFeedback { reaction: "❌".to_owned(), explain: false };
+        let defaults = GuildSettings {
+            original_message: OriginalMessageMode::ReplyOnly,
+            failure_feedback: Some(feedback),
+            ..Default::default()
+        };
+        bot.set_guild_settings(defaults, HashMap::new());
+
+        let mut quiet = message("https://example.com/missing");
+        quiet.id = MessageId::new(3);
+        bot.handle_message(&io, &quiet).await;
+        assert!(matches!(&io.calls()[..], [Call::React { .. }]));
+
+        let mut edited = message("https://example.com/post");
+        edited.id = MessageId::new(3);
+        io.store(edited.clone());
+        bot.handle_message_update(&io, update(&edited)).await;
+
+        match &io.calls()[..] {
+            [Call::Unreact { message, .. }, Call::Send { message: embed, .. }] => {
+                assert_eq!(*message, MessageId::new(3));
+                assert_eq!(embed["embeds"][0]["url"], "https://example.com/post");
+            },
+            calls => panic!("unexpected calls: {calls:?}"),
+        }
     }
 }
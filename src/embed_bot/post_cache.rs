@@ -0,0 +1,436 @@
+use crate::post_grab_api::{spawn_blocking, stable_file_stem, write_atomically, Post};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::File,
+    mem,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+use url::Url;
+
+fn default_ttl_secs() -> u64 {
+    10 * 60
+}
+
+fn default_capacity() -> usize {
+    256
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PostCacheSettings {
+    #[serde(default = "default_ttl_secs")]
+    pub ttl_secs: u64,
+
+    /// by module name, takes precedence over `ttl_secs`, 0 disables caching for the module
+    #[serde(default)]
+    pub module_ttl_secs: HashMap<String, u64>,
+
+    #[serde(default = "default_capacity")]
+    pub capacity: usize,
+
+    /// where cached posts are kept across restarts, in memory only if unset
+    pub directory: Option<PathBuf>,
+}
+
+enum CachedPost {
+    Decoded(Arc<dyn Post>),
+
+    /// loaded from disk, decoded by its module on first use
+    Encoded(serde_json::Value),
+}
+
+struct Entry {
+    module: String,
+    expires_at: SystemTime,
+    post: CachedPost,
+}
+
+/// The file format of a persisted entry
+#[derive(Serialize, Deserialize)]
+struct StoredEntry {
+    module: String,
+    canonical_url: String,
+    expires_at: SystemTime,
+    post: serde_json::Value,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// by canonical url
+    entries: HashMap<String, Entry>,
+
+    /// canonical urls by the urls the posts were requested with
+    aliases: HashMap<String, String>,
+}
+
+fn file_name(canonical_url: &str) -> String {
+    format!("{}.json", stable_file_stem(canonical_url))
+}
+
+/// Remembers scraped posts for a while, so that links shared repeatedly are only scraped once.
+pub struct PostCache {
+    settings: PostCacheSettings,
+    inner: Mutex<Inner>,
+}
+
+impl PostCache {
+    /// Creates the cache, loading the posts persisted in the configured directory.
+    pub fn from_settings(settings: PostCacheSettings) -> anyhow::Result<Self> {
+        let mut inner = Inner::default();
+
+        if let Some(dir) = &settings.directory {
+            std::fs::create_dir_all(dir).with_context(|| format!("Unable to create {}", dir.display()))?;
+
+            for dir_entry in std::fs::read_dir(dir).with_context(|| format!("Unable to read {}", dir.display()))? {
+                let path = dir_entry?.path();
+
+                if path.extension().is_some_and(|ext| ext == "json") {
+                    match Self::load_entry(&path) {
+                        Ok(stored) if stored.expires_at > SystemTime::now() => {
+                            let entry = Entry {
+                                module: stored.module,
+                                expires_at: stored.expires_at,
+                                post: CachedPost::Encoded(stored.post),
+                            };
+
+                            inner.entries.insert(stored.canonical_url, entry);
+                        },
+                        Ok(_) => Self::delete_file(&path),
+                        Err(e) => tracing::warn!("ignoring cached post: {:?}", e),
+                    }
+                }
+            }
+        }
+
+        Ok(Self { settings, inner: Mutex::new(inner) })
+    }
+
+    fn load_entry(path: &Path) -> anyhow::Result<StoredEntry> {
+        let f = File::open(path).with_context(|| format!("Unable to open {}", path.display()))?;
+        serde_json::from_reader(f).with_context(|| format!("Unable to parse {}", path.display()))
+    }
+
+    fn delete_file(path: &Path) {
+        if let Err(e) = std::fs::remove_file(path) {
+            tracing::warn!("unable to delete cached post {}: {}", path.display(), e);
+        }
+    }
+
+    async fn store_entry(dir: &Path, stored: StoredEntry) -> anyhow::Result<()> {
+        let path = dir.join(file_name(&stored.canonical_url));
+
+        spawn_blocking(move || write_atomically(&path, |f| serde_json::to_writer(f, &stored).map_err(Into::into)))
+            .await
+            .context("Writing task failed")?
+    }
+
+    /// Deletes the files of the posts at `canonical_urls`, which have been taken out of the cache.
+    async fn delete_entries(&self, canonical_urls: &[String]) {
+        let Some(dir) = &self.settings.directory else {
+            return;
+        };
+
+        if !canonical_urls.is_empty() {
+            let paths: Vec<_> = canonical_urls.iter().map(|url| dir.join(file_name(url))).collect();
+            let _ = spawn_blocking(move || paths.iter().for_each(|path| Self::delete_file(path))).await;
+        }
+    }
+
+    /// Looks up the post `url` points to, restoring it with `decode` if it was loaded from disk.
+    pub async fn get<F>(&self, url: &Url, decode: F) -> Option<Arc<dyn Post>>
+    where
+        F: FnOnce(serde_json::Value) -> serde_json::Result<Box<dyn Post>>,
+    {
+        let (post, removed) = self.lookup(url, decode);
+
+        if let Some(canonical_url) = removed {
+            self.delete_entries(&[canonical_url]).await;
+        }
+
+        post
+    }
+
+    /// Like [`PostCache::get`], also returning the canonical url of an entry it removed.
+    fn lookup<F>(&self, url: &Url, decode: F) -> (Option<Arc<dyn Post>>, Option<String>)
+    where
+        F: FnOnce(serde_json::Value) -> serde_json::Result<Box<dyn Post>>,
+    {
+        let mut inner = self.inner.lock().unwrap();
+        let canonical_url = inner
+            .aliases
+            .get(url.as_str())
+            .map_or(url.as_str(), String::as_str)
+            .to_owned();
+        let Some(entry) = inner.entries.get_mut(&canonical_url) else {
+            return (None, None);
+        };
+
+        if entry.expires_at <= SystemTime::now() {
+            inner.entries.remove(&canonical_url);
+            return (None, Some(canonical_url));
+        }
+
+        let post = match &mut entry.post {
+            CachedPost::Decoded(post) => Arc::clone(post),
+            CachedPost::Encoded(value) => match decode(mem::take(value)) {
+                Ok(post) => {
+                    let post = Arc::<dyn Post>::from(post);
+                    entry.post = CachedPost::Decoded(Arc::clone(&post));
+                    post
+                },
+                Err(e) => {
+                    tracing::warn!("unable to decode cached {} post {}: {}", entry.module, canonical_url, e);
+                    inner.entries.remove(&canonical_url);
+                    return (None, Some(canonical_url));
+                },
+            },
+        };
+
+        (Some(post), None)
+    }
+
+    /// Remembers `post` of `module`, which was requested with `url`.
+    pub async fn insert(&self, module: &'static str, url: &Url, post: Arc<dyn Post>) {
+        let ttl_secs = self
+            .settings
+            .module_ttl_secs
+            .get(module)
+            .copied()
+            .unwrap_or(self.settings.ttl_secs);
+
+        if ttl_secs == 0 || self.settings.capacity == 0 {
+            return;
+        }
+
+        let canonical_url = post.canonical_url().to_string();
+        let expires_at = SystemTime::now() + Duration::from_secs(ttl_secs);
+
+        if let Some(dir) = &self.settings.directory {
+            let stored = post.encode().map(|value| StoredEntry {
+                module: module.to_owned(),
+                canonical_url: canonical_url.clone(),
+                expires_at,
+                post: value,
+            });
+
+            let stored = match stored {
+                Ok(stored) => Self::store_entry(dir, stored).await,
+                Err(e) => Err(e.into()),
+            };
+
+            if let Err(e) = stored {
+                tracing::warn!("unable to persist cached post {}: {:?}", canonical_url, e);
+            }
+        }
+
+        let evicted = {
+            let mut inner = self.inner.lock().unwrap();
+            let mut evicted = Vec::new();
+
+            while inner.entries.len() >= self.settings.capacity && !inner.entries.contains_key(&canonical_url) {
+                let first_to_expire = inner
+                    .entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.expires_at)
+                    .map(|(key, _)| key.clone());
+
+                match first_to_expire {
+                    Some(key) => {
+                        inner.entries.remove(&key);
+                        evicted.push(key);
+                    },
+                    None => break,
+                }
+            }
+
+            let entry = Entry { module: module.to_owned(), expires_at, post: CachedPost::Decoded(post) };
+            inner.entries.insert(canonical_url.clone(), entry);
+
+            let Inner { entries, aliases } = &mut *inner;
+            aliases.retain(|_, canonical_url| entries.contains_key(canonical_url));
+
+            if url.as_str() != canonical_url {
+                aliases.insert(url.to_string(), canonical_url);
+            }
+
+            evicted
+        };
+
+        self.delete_entries(&evicted).await;
+    }
+
+    /// Forgets the post `url` points to, or all posts if there is no url.
+    /// Returns the number of posts forgotten.
+    pub async fn purge(&self, url: Option<&Url>) -> usize {
+        let purged = {
+            let mut inner = self.inner.lock().unwrap();
+
+            let purged = match url {
+                Some(url) => {
+                    let canonical_url = inner
+                        .aliases
+                        .get(url.as_str())
+                        .map_or(url.as_str(), String::as_str)
+                        .to_owned();
+
+                    match inner.entries.remove(&canonical_url) {
+                        Some(_) => vec![canonical_url],
+                        None => Vec::new(),
+                    }
+                },
+                None => inner.entries.drain().map(|(key, _)| key).collect(),
+            };
+
+            let Inner { entries, aliases } = &mut *inner;
+            aliases.retain(|_, canonical_url| entries.contains_key(canonical_url));
+
+            purged
+        };
+
+        self.delete_entries(&purged).await;
+        purged.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::post_grab_api::{ContentType, CreateResponse, EmbedOptions};
+    use serenity::model::user::User;
+
+    #[derive(Serialize, Deserialize, Debug)]
+    struct TestPost {
+        url: Url,
+    }
+
+    impl Post for TestPost {
+        fn create_embed(&self, _u: &User, _opts: &EmbedOptions, response: CreateResponse) -> CreateResponse {
+            response
+        }
+
+        fn content_type(&self) -> ContentType {
+            ContentType::Text
+        }
+
+        fn canonical_url(&self) -> Url {
+            self.url.clone()
+        }
+    }
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    fn post(canonical_url: &str) -> Arc<dyn Post> {
+        Arc::new(TestPost { url: url(canonical_url) })
+    }
+
+    fn decode(value: serde_json::Value) -> serde_json::Result<Box<dyn Post>> {
+        Ok(Box::new(serde_json::from_value::<TestPost>(value)?))
+    }
+
+    fn cache(capacity: usize, directory: Option<PathBuf>) -> PostCache {
+        PostCache::from_settings(PostCacheSettings {
+            ttl_secs: 60,
+            module_ttl_secs: HashMap::from([("uncached".to_owned(), 0)]),
+            capacity,
+            directory,
+        })
+        .unwrap()
+    }
+
+    async fn cached_url(cache: &PostCache, s: &str) -> Option<Url> {
+        cache.get(&url(s), decode).await.map(|post| post.canonical_url())
+    }
+
+    #[tokio::test]
+    async fn aliases() {
+        let cache = cache(4, None);
+        cache
+            .insert("test", &url("https://short.example/a"), post("https://example.com/a"))
+            .await;
+
+        // links to the same post share the entry, no matter which one was scraped
+        assert_eq!(
+            cached_url(&cache, "https://short.example/a").await,
+            Some(url("https://example.com/a"))
+        );
+        assert_eq!(
+            cached_url(&cache, "https://example.com/a").await,
+            Some(url("https://example.com/a"))
+        );
+        assert_eq!(cached_url(&cache, "https://short.example/b").await, None);
+
+        cache
+            .insert("test", &url("https://other.example/a"), post("https://example.com/a"))
+            .await;
+        assert_eq!(cache.inner.lock().unwrap().entries.len(), 1);
+
+        // purging through an alias forgets the post for all of its links
+        assert_eq!(cache.purge(Some(&url("https://other.example/a"))).await, 1);
+        assert_eq!(cached_url(&cache, "https://short.example/a").await, None);
+        assert_eq!(cached_url(&cache, "https://example.com/a").await, None);
+        assert!(cache.inner.lock().unwrap().aliases.is_empty());
+    }
+
+    #[tokio::test]
+    async fn evicted_aliases() {
+        let cache = cache(1, None);
+        cache
+            .insert("test", &url("https://short.example/a"), post("https://example.com/a"))
+            .await;
+        cache
+            .insert("test", &url("https://short.example/b"), post("https://example.com/b"))
+            .await;
+
+        assert_eq!(cached_url(&cache, "https://short.example/a").await, None);
+        assert_eq!(
+            cached_url(&cache, "https://short.example/b").await,
+            Some(url("https://example.com/b"))
+        );
+
+        let inner = cache.inner.lock().unwrap();
+        assert_eq!(inner.aliases.keys().collect::<Vec<_>>(), ["https://short.example/b"]);
+    }
+
+    #[tokio::test]
+    async fn module_ttl() {
+        let cache = cache(4, None);
+        cache
+            .insert(
+                "uncached",
+                &url("https://short.example/a"),
+                post("https://example.com/a"),
+            )
+            .await;
+
+        assert_eq!(cached_url(&cache, "https://short.example/a").await, None);
+        assert!(cache.inner.lock().unwrap().aliases.is_empty());
+    }
+
+    #[tokio::test]
+    async fn reload() {
+        let dir = tempfile::tempdir().unwrap();
+
+        {
+            let cache = cache(4, Some(dir.path().to_owned()));
+            cache
+                .insert("test", &url("https://short.example/a"), post("https://example.com/a"))
+                .await;
+        }
+
+        // only the canonical url is persisted, aliases are learned again when they are scraped
+        let cache = cache(4, Some(dir.path().to_owned()));
+        assert_eq!(
+            cached_url(&cache, "https://example.com/a").await,
+            Some(url("https://example.com/a"))
+        );
+        assert_eq!(cached_url(&cache, "https://short.example/a").await, None);
+
+        assert_eq!(cache.purge(None).await, 1);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,219 @@
+use serde::{Deserialize, Serialize};
+use serenity::model::id::{ChannelId, GuildId, UserId};
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Number of buckets above which full ones are forgotten
+const PRUNE_THRESHOLD: usize = 4096;
+
+/// A token bucket holding up to `capacity` embeds, refilled by one every `refill_secs`
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+pub struct BucketSettings {
+    pub capacity: u32,
+    pub refill_secs: f64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct RateLimits {
+    pub user: Option<BucketSettings>,
+    pub channel: Option<BucketSettings>,
+    pub guild: Option<BucketSettings>,
+}
+
+/// Every module has its own budget, `modules` overrides the limits of specific ones
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct RateLimitSettings {
+    #[serde(flatten)]
+    pub default: RateLimits,
+
+    /// by module name
+    #[serde(default)]
+    pub modules: HashMap<String, RateLimits>,
+}
+
+/// Who wants a post embedded where
+pub struct Scope {
+    pub user: UserId,
+    pub channel: ChannelId,
+    pub guild: Option<GuildId>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+enum BucketKey {
+    User(UserId),
+    Channel(ChannelId),
+    Guild(GuildId),
+}
+
+struct Bucket {
+    settings: BucketSettings,
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl Bucket {
+    fn new(settings: BucketSettings) -> Self {
+        Self { settings, tokens: settings.capacity as f64, updated_at: Instant::now() }
+    }
+
+    fn refill(&mut self) {
+        let refilled = self.updated_at.elapsed().as_secs_f64() / self.settings.refill_secs;
+        self.tokens = (self.tokens + refilled).min(self.settings.capacity as f64);
+        self.updated_at = Instant::now();
+    }
+
+    /// How long until a token is available, refilling first.
+    fn wait_time(&mut self) -> Duration {
+        self.refill();
+        Duration::from_secs_f64(((1.0 - self.tokens) * self.settings.refill_secs).max(0.0))
+    }
+
+    fn is_full(&self) -> bool {
+        self.tokens >= self.settings.capacity as f64
+    }
+}
+
+pub struct RateLimiter {
+    settings: RateLimitSettings,
+    buckets: Mutex<HashMap<(&'static str, BucketKey), Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn from_settings(settings: RateLimitSettings) -> Self {
+        Self { settings, buckets: Mutex::new(HashMap::new()) }
+    }
+
+    /// Takes a token from every bucket of `scope` for `module`,
+    /// or returns how long to wait if any of them is empty.
+    pub fn acquire(&self, module: &'static str, scope: &Scope) -> Result<(), Duration> {
+        let limits = self.settings.modules.get(module).unwrap_or(&self.settings.default);
+
+        let keys = [
+            limits.user.map(|l| (BucketKey::User(scope.user), l)),
+            limits.channel.map(|l| (BucketKey::Channel(scope.channel), l)),
+            scope.guild.zip(limits.guild).map(|(g, l)| (BucketKey::Guild(g), l)),
+        ];
+
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.len() > PRUNE_THRESHOLD {
+            buckets.retain(|_, bucket| {
+                bucket.refill();
+                !bucket.is_full()
+            });
+        }
+
+        let wait = keys
+            .iter()
+            .flatten()
+            .map(|&(key, settings)| {
+                buckets
+                    .entry((module, key))
+                    .or_insert_with(|| Bucket::new(settings))
+                    .wait_time()
+            })
+            .max()
+            .unwrap_or_default();
+
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+
+        for (key, _) in keys.iter().flatten() {
+            if let Some(bucket) = buckets.get_mut(&(module, *key)) {
+                bucket.tokens -= 1.0;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scope(user: u64, channel: u64) -> Scope {
+        Scope {
+            user: UserId::new(user),
+            channel: ChannelId::new(channel),
+            guild: Some(GuildId::new(1)),
+        }
+    }
+
+    fn limits(user: Option<BucketSettings>, channel: Option<BucketSettings>) -> RateLimits {
+        RateLimits { user, channel, guild: None }
+    }
+
+    #[test]
+    fn buckets() {
+        let bucket = BucketSettings { capacity: 2, refill_secs: 3600.0 };
+        let limiter = RateLimiter::from_settings(RateLimitSettings {
+            default: limits(Some(bucket), None),
+            modules: HashMap::new(),
+        });
+
+        assert!(limiter.acquire("a", &scope(1, 1)).is_ok());
+        assert!(limiter.acquire("a", &scope(1, 2)).is_ok());
+
+        let wait = limiter.acquire("a", &scope(1, 1)).unwrap_err();
+        assert!(wait > Duration::from_secs(3500) && wait <= Duration::from_secs(3600));
+
+        // other users and modules have budgets of their own
+        assert!(limiter.acquire("a", &scope(2, 1)).is_ok());
+        assert!(limiter.acquire("b", &scope(1, 1)).is_ok());
+    }
+
+    #[test]
+    fn refill() {
+        let bucket = BucketSettings { capacity: 1, refill_secs: 0.05 };
+        let limiter = RateLimiter::from_settings(RateLimitSettings {
+            default: limits(Some(bucket), None),
+            modules: HashMap::new(),
+        });
+
+        assert!(limiter.acquire("a", &scope(1, 1)).is_ok());
+        assert!(limiter.acquire("a", &scope(1, 1)).is_err());
+
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(limiter.acquire("a", &scope(1, 1)).is_ok());
+        assert!(limiter.acquire("a", &scope(1, 1)).is_err());
+    }
+
+    #[test]
+    fn module_override() {
+        let strict = BucketSettings { capacity: 1, refill_secs: 3600.0 };
+        let limiter = RateLimiter::from_settings(RateLimitSettings {
+            default: limits(Some(strict), None),
+            modules: HashMap::from([("b".to_owned(), limits(None, Some(strict)))]),
+        });
+
+        // the override replaces the default limits instead of adding to them
+        assert!(limiter.acquire("b", &scope(1, 1)).is_ok());
+        assert!(limiter.acquire("b", &scope(1, 2)).is_ok());
+        assert!(limiter.acquire("b", &scope(2, 1)).is_err());
+
+        assert!(limiter.acquire("a", &scope(1, 1)).is_ok());
+        assert!(limiter.acquire("a", &scope(1, 2)).is_err());
+    }
+
+    #[test]
+    fn failed_acquire_takes_nothing() {
+        let user = BucketSettings { capacity: 2, refill_secs: 3600.0 };
+        let channel = BucketSettings { capacity: 1, refill_secs: 3600.0 };
+        let limiter = RateLimiter::from_settings(RateLimitSettings {
+            default: limits(Some(user), Some(channel)),
+            modules: HashMap::new(),
+        });
+
+        assert!(limiter.acquire("a", &scope(1, 1)).is_ok());
+        assert!(limiter.acquire("a", &scope(1, 1)).is_err());
+
+        // the user bucket was left alone by the rejected attempt
+        assert!(limiter.acquire("a", &scope(1, 2)).is_ok());
+        assert!(limiter.acquire("a", &scope(1, 3)).is_err());
+    }
+}
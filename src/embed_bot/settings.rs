@@ -1,11 +1,46 @@
+use super::{
+    CircuitBreakerSettings, ContentTypeProbeSettings, GuildSettings, JobQueueSettings, PostCacheSettings,
+    RateLimitSettings,
+};
 use crate::post_grab_api;
 use serde::{Deserialize, Serialize};
-use std::fmt::{Debug, Formatter};
+use serenity::model::id::GuildId;
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Formatter},
+    path::PathBuf,
+};
 
 #[derive(Serialize, Deserialize)]
 pub struct Settings {
     pub discord_token: String,
     pub modules: Option<Modules>,
+    pub content_type_probe: Option<ContentTypeProbeSettings>,
+
+    /// token buckets limiting how often posts are embedded, unlimited if unset
+    pub rate_limits: Option<RateLimitSettings>,
+
+    /// limits how many posts are fetched at once per module, unlimited if unset
+    pub job_queue: Option<JobQueueSettings>,
+
+    /// remembers scraped posts for a while, disabled if unset
+    pub post_cache: Option<PostCacheSettings>,
+
+    /// caches the http responses posts are scraped from, disabled if unset
+    pub http_cache: Option<post_grab_api::http_cache::HttpCacheSettings>,
+
+    /// stops calling modules that keep failing for a while, disabled if unset
+    pub circuit_breaker: Option<CircuitBreakerSettings>,
+
+    /// directory for state that has to survive restarts, kept in memory only if unset
+    pub state_dir: Option<PathBuf>,
+
+    /// settings for guilds not listed in `guilds`, and for direct messages
+    #[serde(default)]
+    pub guild_defaults: GuildSettings,
+
+    #[serde(default)]
+    pub guilds: HashMap<GuildId, GuildSettings>,
 }
 
 impl Debug for Settings {
@@ -13,6 +48,15 @@ impl Debug for Settings {
         f.debug_struct("Settings")
             .field("discord_token", &"[REDACTED]")
             .field("modules", &self.modules)
+            .field("content_type_probe", &self.content_type_probe)
+            .field("rate_limits", &self.rate_limits)
+            .field("job_queue", &self.job_queue)
+            .field("post_cache", &self.post_cache)
+            .field("http_cache", &self.http_cache)
+            .field("circuit_breaker", &self.circuit_breaker)
+            .field("state_dir", &self.state_dir)
+            .field("guild_defaults", &self.guild_defaults)
+            .field("guilds", &self.guilds)
             .finish()
     }
 }
@@ -28,6 +72,9 @@ pub struct Modules {
     #[cfg(feature = "svg")]
     pub svg: Option<post_grab_api::svg::ApiSettings>,
 
+    #[cfg(feature = "transcode")]
+    pub transcode: Option<post_grab_api::transcode::ApiSettings>,
+
     #[cfg(feature = "twitter")]
     pub twitter: Option<post_grab_api::twitter::ApiSettings>,
 }
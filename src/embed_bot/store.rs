@@ -0,0 +1,338 @@
+use crate::post_grab_api::write_atomically;
+use anyhow::Context;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    hash::Hash,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
+    thread::JoinHandle,
+    time::{Duration, SystemTime},
+};
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Entry<V> {
+    inserted_at: SystemTime,
+    value: V,
+}
+
+/// A line of the journal a [`PersistentMap`] is stored in.
+#[derive(Serialize, Deserialize)]
+enum Change<K, V> {
+    Insert(K, Entry<V>),
+    Remove(K),
+}
+
+type Map<K, V> = Arc<Mutex<HashMap<K, Entry<V>>>>;
+
+/// Appends changes to the journal file on a thread of its own, so that they never wait for the disk.
+struct Journal<K, V> {
+    changes: Option<mpsc::Sender<Change<K, V>>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl<K, V> Drop for Journal<K, V> {
+    fn drop(&mut self) {
+        // closing the channel lets the writer finish the pending changes and exit
+        self.changes.take();
+
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+/// A bounded map that is mirrored to a journal file so that its contents survive restarts.
+/// Once full, the oldest entries are dropped first.
+pub struct PersistentMap<K, V> {
+    capacity: usize,
+    map: Map<K, V>,
+    journal: Option<Journal<K, V>>,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> PersistentMap<K, V> {
+    pub fn in_memory(capacity: usize) -> Self {
+        Self { capacity, map: Arc::new(Mutex::new(HashMap::new())), journal: None }
+    }
+
+    fn changed(&self, change: Change<K, V>) {
+        if let Some(changes) = self.journal.as_ref().and_then(|journal| journal.changes.as_ref()) {
+            let _ = changes.send(change);
+        }
+    }
+
+    pub fn insert(&self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut map = self.map.lock().unwrap();
+
+        for evicted in make_room(&mut map, self.capacity, Some(&key)) {
+            self.changed(Change::Remove(evicted));
+        }
+
+        let entry = Entry { inserted_at: SystemTime::now(), value };
+        self.changed(Change::Insert(key.clone(), entry.clone()));
+        map.insert(key, entry);
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.map.lock().unwrap().get(key).map(|entry| entry.value.clone())
+    }
+
+    /// Like [`PersistentMap::get`], but only if the value was stored less than `max_age` ago.
+    pub fn get_fresh(&self, key: &K, max_age: Duration) -> Option<V> {
+        self.map
+            .lock()
+            .unwrap()
+            .get(key)
+            .filter(|entry| entry.inserted_at.elapsed().unwrap_or_default() < max_age)
+            .map(|entry| entry.value.clone())
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        let mut map = self.map.lock().unwrap();
+        let removed = map.remove(key).map(|entry| entry.value);
+
+        if removed.is_some() {
+            self.changed(Change::Remove(key.clone()));
+        }
+
+        removed
+    }
+
+    /// Applies `f` to the value stored for `key`, if there is one.
+    pub fn update<R>(&self, key: &K, f: impl FnOnce(&mut V) -> R) -> Option<R> {
+        let mut map = self.map.lock().unwrap();
+        let entry = map.get_mut(key)?;
+        let res = f(&mut entry.value);
+
+        self.changed(Change::Insert(key.clone(), entry.clone()));
+        Some(res)
+    }
+}
+
+impl<K, V> PersistentMap<K, V>
+where
+    K: Eq + Hash + Clone + Serialize + DeserializeOwned + Send + 'static,
+    V: Clone + Serialize + DeserializeOwned + Send + 'static,
+{
+    /// Loads the map from `path`, starting out empty if the file does not exist yet.
+    pub fn load(path: PathBuf, capacity: usize) -> anyhow::Result<Self> {
+        let mut map: HashMap<K, Entry<V>> = HashMap::new();
+        let mut lines = 0;
+        let mut torn = false;
+
+        match File::open(&path) {
+            Ok(f) => {
+                let mut journal = BufReader::new(f).lines().peekable();
+
+                while let Some(line) = journal.next() {
+                    let line = line.with_context(|| format!("Unable to read {}", path.display()))?;
+                    lines += 1;
+
+                    match serde_json::from_str(&line) {
+                        Ok(Change::Insert(key, entry)) => {
+                            map.insert(key, entry);
+                        },
+                        Ok(Change::Remove(key)) => {
+                            map.remove(&key);
+                        },
+                        // the last change may have been cut off by a crash
+                        Err(e) if journal.peek().is_none() => {
+                            tracing::warn!("dropping incomplete change at the end of {}: {}", path.display(), e);
+                            torn = true;
+                        },
+                        Err(e) => return Err(e).with_context(|| format!("Unable to parse {}", path.display())),
+                    }
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e).with_context(|| format!("Unable to open {}", path.display())),
+        }
+
+        make_room(&mut map, capacity, None);
+
+        // start over from a clean journal, so that new changes are not appended to the broken line
+        if torn {
+            let snapshot: Vec<_> = map
+                .iter()
+                .map(|(key, entry)| Change::Insert(key.clone(), entry.clone()))
+                .collect();
+            compact(&path, &snapshot)?;
+            lines = snapshot.len();
+        }
+
+        let map = Arc::new(Mutex::new(map));
+        let (changes, pending) = mpsc::channel();
+
+        let writer = {
+            let map = map.clone();
+            std::thread::spawn(move || write_journal(&path, capacity, lines, &map, pending))
+        };
+
+        Ok(Self {
+            capacity,
+            map,
+            journal: Some(Journal { changes: Some(changes), writer: Some(writer) }),
+        })
+    }
+}
+
+/// Drops the oldest entries until there is room for `key`, returning the dropped keys.
+fn make_room<K: Eq + Hash + Clone, V>(map: &mut HashMap<K, Entry<V>>, capacity: usize, key: Option<&K>) -> Vec<K> {
+    let mut evicted = Vec::new();
+    let needed = usize::from(key.is_some_and(|key| !map.contains_key(key)));
+
+    while map.len() + needed > capacity {
+        let oldest = map
+            .iter()
+            .min_by_key(|(_, entry)| entry.inserted_at)
+            .map(|(key, _)| key.clone());
+
+        match oldest {
+            Some(oldest) => {
+                map.remove(&oldest);
+                evicted.push(oldest);
+            },
+            None => break,
+        }
+    }
+
+    evicted
+}
+
+/// Appends the changes arriving on `pending` to the journal at `path` in batches,
+/// rewriting it from the current contents of `map` once it has grown to twice the capacity.
+fn write_journal<K, V>(
+    path: &Path,
+    capacity: usize,
+    mut lines: usize,
+    map: &Map<K, V>,
+    pending: mpsc::Receiver<Change<K, V>>,
+) where
+    K: Clone + Serialize,
+    V: Clone + Serialize,
+{
+    while let Ok(change) = pending.recv() {
+        let batch: Vec<_> = std::iter::once(change).chain(pending.try_iter()).collect();
+
+        // changes still waiting in the channel are already part of the snapshot,
+        // appending them after it again does not alter the result of replaying the journal
+        let res = if lines + batch.len() > 2 * capacity.max(1) {
+            let snapshot = map
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(key, entry)| Change::Insert(key.clone(), entry.clone()))
+                .collect::<Vec<_>>();
+
+            lines = snapshot.len();
+            compact(path, &snapshot)
+        } else {
+            lines += batch.len();
+            append(path, &batch)
+        };
+
+        if let Err(e) = res {
+            tracing::error!("unable to persist state: {:?}", e);
+        }
+    }
+}
+
+fn write_changes<K: Serialize, V: Serialize>(f: &mut impl Write, changes: &[Change<K, V>]) -> anyhow::Result<()> {
+    for change in changes {
+        serde_json::to_writer(&mut *f, change)?;
+        f.write_all(b"\n")?;
+    }
+
+    Ok(())
+}
+
+fn append<K: Serialize, V: Serialize>(path: &Path, changes: &[Change<K, V>]) -> anyhow::Result<()> {
+    let f = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Unable to open {}", path.display()))?;
+
+    let mut f = BufWriter::new(f);
+    write_changes(&mut f, changes)?;
+    f.flush()?;
+    Ok(())
+}
+
+fn compact<K: Serialize, V: Serialize>(path: &Path, snapshot: &[Change<K, V>]) -> anyhow::Result<()> {
+    write_atomically(path, |f| write_changes(f, snapshot))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn journal_lines(path: &Path) -> usize {
+        std::fs::read_to_string(path).unwrap().lines().count()
+    }
+
+    #[test]
+    fn reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("map.json");
+
+        {
+            let map = PersistentMap::load(path.clone(), 2).unwrap();
+            map.insert(1, "one".to_owned());
+            map.insert(2, "two".to_owned());
+            map.update(&2, |v| v.push('!'));
+            map.insert(3, "three".to_owned());
+            map.remove(&3);
+        }
+
+        let map = PersistentMap::<u32, String>::load(path, 2).unwrap();
+        assert_eq!(map.get(&1), None);
+        assert_eq!(map.get(&2).as_deref(), Some("two!"));
+        assert_eq!(map.get(&3), None);
+    }
+
+    #[test]
+    fn compaction() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("map.json");
+
+        {
+            let map = PersistentMap::load(path.clone(), 4).unwrap();
+            for i in 0..100 {
+                map.insert(i % 2, i);
+            }
+        }
+
+        assert!(journal_lines(&path) <= 8);
+
+        let map = PersistentMap::<u32, u32>::load(path, 4).unwrap();
+        assert_eq!((map.get(&0), map.get(&1)), (Some(98), Some(99)));
+    }
+
+    #[test]
+    fn incomplete_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("map.json");
+
+        {
+            let map = PersistentMap::load(path.clone(), 4).unwrap();
+            map.insert(1, 1);
+        }
+
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"Insert\":[2,")
+            .unwrap();
+
+        let map = PersistentMap::<u32, u32>::load(path, 4).unwrap();
+        assert_eq!((map.get(&1), map.get(&2)), (Some(1), None));
+    }
+}
@@ -0,0 +1,47 @@
+use super::store::PersistentMap;
+use std::{hash::Hash, time::Duration};
+
+/// A bounded in-memory map whose entries expire after a fixed time.
+pub struct TtlCache<K, V> {
+    ttl: Duration,
+    map: PersistentMap<K, V>,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> TtlCache<K, V> {
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        Self { ttl, map: PersistentMap::in_memory(capacity) }
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.map.get_fresh(key, self.ttl)
+    }
+
+    /// Expired entries are the oldest, so they are the first to make room for new ones.
+    pub fn insert(&self, key: K, value: V) {
+        self.map.insert(key, value);
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        self.map.remove(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expiry() {
+        let cache = TtlCache::new(Duration::from_secs(60), 2);
+        cache.insert(1, "one");
+        assert_eq!(cache.get(&1), Some("one"));
+
+        let expired = TtlCache::new(Duration::ZERO, 2);
+        expired.insert(1, "one");
+        assert_eq!(expired.get(&1), None);
+
+        let disabled = TtlCache::new(Duration::from_secs(60), 0);
+        disabled.insert(1, "one");
+        assert_eq!(disabled.get(&1), None);
+    }
+}
@@ -39,6 +39,10 @@ async fn main() {
     let embed_bot = {
         let mut e = EmbedBot::new();
 
+        if let Some(state_dir) = &settings.state_dir {
+            e.set_state_dir(state_dir).expect("access to state directory");
+        }
+
         if let Some(modules) = settings.modules {
             #[cfg(feature = "reddit")]
             if let Some(settings) = modules.reddit {
@@ -55,12 +59,43 @@ async fn main() {
                 e.register_api(post_grab_api::svg::Api::from_settings(settings));
             }
 
+            #[cfg(feature = "transcode")]
+            if let Some(settings) = modules.transcode {
+                e.register_api(post_grab_api::transcode::Api::from_settings(settings));
+            }
+
             #[cfg(feature = "twitter")]
             if let Some(settings) = modules.twitter {
                 e.register_api(post_grab_api::twitter::Api::from_settings(settings));
             }
         }
 
+        if let Some(settings) = settings.content_type_probe {
+            e.enable_content_type_probe(settings);
+        }
+
+        if let Some(settings) = settings.rate_limits {
+            e.enable_rate_limits(settings);
+        }
+
+        if let Some(settings) = settings.job_queue {
+            e.enable_job_queue(settings);
+        }
+
+        if let Some(settings) = settings.circuit_breaker {
+            e.enable_circuit_breaker(settings);
+        }
+
+        if let Some(settings) = settings.http_cache {
+            post_grab_api::http_cache::enable(settings).expect("access to http cache directory");
+        }
+
+        if let Some(settings) = settings.post_cache {
+            e.enable_post_cache(settings).expect("access to post cache directory");
+        }
+
+        e.set_guild_settings(settings.guild_defaults, settings.guilds);
+
         e
     };
 
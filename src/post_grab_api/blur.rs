@@ -0,0 +1,95 @@
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "blur")]
+use {
+    super::{spawn_blocking, tempfile_attachment, wget},
+    anyhow::Context,
+    image::ImageFormat,
+    serenity::builder::CreateAttachment,
+    url::Url,
+};
+
+/// Previews are scaled down to fit into a square of this size before blurring,
+/// so that the blur radius has the same effect regardless of the original resolution.
+#[cfg(feature = "blur")]
+const PREVIEW_SIZE: u32 = 640;
+
+fn default_radius() -> f32 {
+    24.0
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BlurSettings {
+    /// standard deviation of the gaussian blur, in pixels of the scaled down preview
+    #[serde(default = "default_radius")]
+    pub radius: f32,
+}
+
+/// Downloads the image at `url` and turns it into a heavily blurred PNG attachment named `filename`.
+#[cfg(feature = "blur")]
+pub async fn blurred_preview(url: Url, settings: &BlurSettings, filename: &str) -> anyhow::Result<CreateAttachment> {
+    let data = wget(url).await?.error_for_status()?.bytes().await?;
+    let radius = settings.radius;
+
+    let img = spawn_blocking(move || {
+        image::load_from_memory(&data)
+            .context("Unable to decode image")
+            .map(|img| img.thumbnail(PREVIEW_SIZE, PREVIEW_SIZE).blur(radius))
+    })
+    .await??;
+
+    tempfile_attachment(filename, move |path| {
+        img.save_with_format(path, ImageFormat::Png)
+            .context("Unable to save PNG")
+    })
+    .await
+}
+
+#[cfg(all(test, feature = "blur"))]
+mod tests {
+    use super::*;
+    use crate::post_grab_api::{http_response, serve};
+
+    /// black on the left half, white on the right
+    fn halves() -> Vec<u8> {
+        let img = image::GrayImage::from_fn(800, 400, |x, _| image::Luma([if x < 400 { 0 } else { 255 }]));
+        let mut data = std::io::Cursor::new(Vec::new());
+        img.write_to(&mut data, ImageFormat::Png).unwrap();
+        data.into_inner()
+    }
+
+    #[tokio::test]
+    async fn blur() {
+        let body = halves();
+        let url = serve(move |_| http_response("200 OK\r\nContent-Type: image/png", &body));
+
+        let settings = BlurSettings { radius: default_radius() };
+        let attachment = blurred_preview(url, &settings, "preview.png").await.unwrap();
+        assert_eq!(attachment.filename, "preview.png");
+
+        let img = image::load_from_memory_with_format(&attachment.data, ImageFormat::Png)
+            .unwrap()
+            .into_luma8();
+        assert_eq!(img.dimensions(), (PREVIEW_SIZE, PREVIEW_SIZE / 2));
+
+        // the edge is smeared, while the far ends keep their color
+        let [edge] = img.get_pixel(PREVIEW_SIZE / 2, 100).0;
+        assert!((50..200).contains(&edge), "edge is {edge}");
+        assert!(img.get_pixel(0, 100).0[0] < 50);
+        assert!(img.get_pixel(PREVIEW_SIZE - 1, 100).0[0] > 200);
+    }
+
+    #[tokio::test]
+    async fn malformed_images() {
+        let settings = BlurSettings { radius: default_radius() };
+        let png = halves();
+
+        for body in [
+            png[..png.len() / 2].to_vec(),
+            b"<html>not an image</html>".to_vec(),
+            Vec::new(),
+        ] {
+            let url = serve(move |_| http_response("200 OK\r\nContent-Type: image/png", &body));
+            assert!(blurred_preview(url, &settings, "preview.png").await.is_err());
+        }
+    }
+}
@@ -0,0 +1,247 @@
+use serde::{Deserialize, Serialize};
+use serenity::builder::{CreateAttachment, CreateEmbedFooter};
+#[cfg(feature = "collage")]
+use {
+    super::{spawn_blocking, tempfile_attachment, wget},
+    anyhow::Context,
+    resvg::tiny_skia,
+    url::Url,
+};
+
+fn default_max_tiles() -> usize {
+    9
+}
+
+fn default_max_width() -> u32 {
+    1600
+}
+
+fn default_max_height() -> u32 {
+    1600
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CollageSettings {
+    #[serde(default = "default_max_tiles")]
+    pub max_tiles: usize,
+
+    #[serde(default = "default_max_width")]
+    pub max_width: u32,
+
+    #[serde(default = "default_max_height")]
+    pub max_height: u32,
+}
+
+/// A grid of (some of) the images of a gallery, rendered into a single attachment.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Collage {
+    #[serde(with = "super::serde_attachment")]
+    pub attachment: CreateAttachment,
+    pub shown: usize,
+    pub total: usize,
+}
+
+impl Collage {
+    pub fn footer(&self) -> CreateEmbedFooter {
+        CreateEmbedFooter::new(format!("{}/{} images", self.shown, self.total))
+    }
+}
+
+#[cfg(feature = "collage")]
+fn to_pixmap(img: image::DynamicImage) -> Option<tiny_skia::Pixmap> {
+    let img = img.into_rgba8();
+    let mut pix = tiny_skia::Pixmap::new(img.width(), img.height())?;
+
+    for (dst, src) in pix.pixels_mut().iter_mut().zip(img.pixels()) {
+        let [r, g, b, a] = src.0;
+        *dst = tiny_skia::ColorU8::from_rgba(r, g, b, a).premultiply();
+    }
+
+    Some(pix)
+}
+
+#[cfg(feature = "collage")]
+fn compose(images: &[image::DynamicImage], settings: &CollageSettings) -> anyhow::Result<tiny_skia::Pixmap> {
+    anyhow::ensure!(!images.is_empty(), "a collage needs at least one image");
+
+    let n = images.len() as u32;
+    let cols = (n as f64).sqrt().ceil() as u32;
+    let rows = n.div_ceil(cols);
+
+    let cell_width = settings.max_width / cols;
+    let cell_height = settings.max_height / rows;
+
+    let mut canvas = tiny_skia::Pixmap::new(cell_width * cols, cell_height * rows)
+        .ok_or_else(|| anyhow::anyhow!("invalid collage size {}x{}", settings.max_width, settings.max_height))?;
+
+    for (ix, img) in images.iter().enumerate() {
+        let (col, row) = (ix as u32 % cols, ix as u32 / cols);
+
+        let Some(tile) = to_pixmap(img.thumbnail(cell_width, cell_height)) else {
+            continue;
+        };
+
+        // center the tile in its cell
+        let x = col * cell_width + (cell_width - tile.width()) / 2;
+        let y = row * cell_height + (cell_height - tile.height()) / 2;
+
+        canvas.draw_pixmap(
+            x as i32,
+            y as i32,
+            tile.as_ref(),
+            &tiny_skia::PixmapPaint::default(),
+            tiny_skia::Transform::identity(),
+            None,
+        );
+    }
+
+    Ok(canvas)
+}
+
+/// Downloads up to `settings.max_tiles` of `urls` concurrently and arranges them in a grid.
+/// Images that fail to download or decode are left out.
+#[cfg(feature = "collage")]
+pub async fn render_collage(urls: &[Url], settings: &CollageSettings, filename: &str) -> anyhow::Result<Collage> {
+    let mut downloads = tokio::task::JoinSet::new();
+
+    for (ix, url) in urls.iter().take(settings.max_tiles).cloned().enumerate() {
+        downloads.spawn(async move {
+            let data = wget(url.clone()).await?.error_for_status()?.bytes().await?;
+            anyhow::Ok((ix, url, data))
+        });
+    }
+
+    let mut downloaded = Vec::new();
+    while let Some(res) = downloads.join_next().await {
+        match res? {
+            Ok(download) => downloaded.push(download),
+            Err(e) => tracing::warn!("leaving image out of collage: {:?}", e),
+        }
+    }
+
+    downloaded.sort_by_key(|(ix, _, _)| *ix);
+
+    let settings = settings.clone();
+    let (canvas, shown) = spawn_blocking(move || {
+        let images: Vec<_> = downloaded
+            .into_iter()
+            .filter_map(|(_, url, data)| match image::load_from_memory(&data) {
+                Ok(img) => Some(img),
+                Err(e) => {
+                    tracing::warn!("leaving {} out of collage: {}", url, e);
+                    None
+                },
+            })
+            .collect();
+
+        anyhow::ensure!(!images.is_empty(), "none of the gallery images could be loaded");
+
+        compose(&images, &settings).map(|canvas| (canvas, images.len()))
+    })
+    .await??;
+
+    let attachment = tempfile_attachment(filename, move |path| {
+        canvas.save_png(path).context("Unable to save PNG")
+    })
+    .await?;
+
+    Ok(Collage { attachment, shown, total: urls.len() })
+}
+
+#[cfg(all(test, feature = "collage"))]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    fn settings(max_tiles: usize) -> CollageSettings {
+        CollageSettings { max_tiles, max_width: 300, max_height: 200 }
+    }
+
+    fn red() -> image::DynamicImage {
+        image::RgbaImage::from_pixel(50, 50, image::Rgba([255, 0, 0, 255])).into()
+    }
+
+    fn png(img: &image::DynamicImage) -> Vec<u8> {
+        let mut data = std::io::Cursor::new(Vec::new());
+        img.write_to(&mut data, image::ImageFormat::Png).unwrap();
+        data.into_inner()
+    }
+
+    #[test]
+    fn grid() {
+        // 5 tiles need 3 columns and 2 rows, leaving the last cell empty
+        let canvas = compose(&vec![red(); 5], &settings(9)).unwrap();
+        assert_eq!((canvas.width(), canvas.height()), (300, 200));
+
+        let filled = |x, y| canvas.pixel(x, y).unwrap().alpha() > 0;
+        assert!(filled(50, 50));
+        assert!(filled(150, 150));
+        assert!(!filled(250, 150));
+    }
+
+    #[tokio::test]
+    async fn tile_cap() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let body = png(&red());
+        let base = {
+            let requests = requests.clone();
+            crate::post_grab_api::serve(move |_| {
+                requests.fetch_add(1, Ordering::SeqCst);
+                crate::post_grab_api::http_response("200 OK\r\nContent-Type: image/png", &body)
+            })
+        };
+
+        let urls: Vec<_> = (0..5).map(|i| base.join(&format!("{}.png", i)).unwrap()).collect();
+        let collage = render_collage(&urls, &settings(2), "collage.png").await.unwrap();
+
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        assert_eq!((collage.shown, collage.total), (2, 5));
+        assert_eq!(serde_json::to_value(collage.footer()).unwrap()["text"], "2/5 images");
+    }
+
+    #[test]
+    fn degenerate_grid() {
+        assert!(compose(&[], &settings(9)).is_err());
+
+        // more columns than pixels
+        let narrow = CollageSettings { max_tiles: 9, max_width: 2, max_height: 200 };
+        assert!(compose(&vec![red(); 5], &narrow).is_err());
+
+        // empty images are left out
+        let empty = image::DynamicImage::new_rgba8(0, 0);
+        let canvas = compose(&[empty, red()], &settings(9)).unwrap();
+        assert_eq!(canvas.pixel(50, 100).unwrap().alpha(), 0);
+        assert!(canvas.pixel(200, 100).unwrap().alpha() > 0);
+    }
+
+    #[tokio::test]
+    async fn malformed_images() {
+        let body = png(&red());
+        let base = crate::post_grab_api::serve(move |request| {
+            let body = match request.split_whitespace().nth(1) {
+                Some("/good.png") => &body[..],
+                Some("/truncated.png") => &body[..body.len() / 2],
+                _ => b"<html>not an image</html>",
+            };
+
+            crate::post_grab_api::http_response("200 OK\r\nContent-Type: image/png", body)
+        });
+
+        let url = |path: &str| base.join(path).unwrap();
+
+        let collage = render_collage(
+            &[url("truncated.png"), url("good.png"), url("garbage.png")],
+            &settings(9),
+            "collage.png",
+        )
+        .await
+        .unwrap();
+        assert_eq!((collage.shown, collage.total), (1, 3));
+
+        let res = render_collage(&[url("truncated.png"), url("garbage.png")], &settings(9), "collage.png").await;
+        assert!(res.is_err());
+    }
+}
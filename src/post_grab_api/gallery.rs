@@ -0,0 +1,156 @@
+use super::{
+    collage::{Collage, CollageSettings},
+    CreateResponse,
+};
+use serde::{Deserialize, Serialize};
+use serenity::{
+    builder::{CreateActionRow, CreateButton, CreateEmbed, CreateEmbedFooter},
+    model::id::UserId,
+};
+use url::Url;
+
+pub const PAGER_PREV_ID: &str = "gallery-prev";
+pub const PAGER_NEXT_ID: &str = "gallery-next";
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct PagerSettings {
+    /// allow everyone to page through the gallery instead of only the person who posted it
+    #[serde(default)]
+    pub anyone_can_page: bool,
+}
+
+/// How posts consisting of multiple images are displayed.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub enum GalleryMode {
+    /// list the image urls and let discord embed them
+    #[default]
+    Links,
+    Collage(CollageSettings),
+    Pager(PagerSettings),
+}
+
+impl GalleryMode {
+    /// Falls back to the `collage` key that configs used before `gallery` existed.
+    pub fn or_legacy_collage(self, collage: Option<CollageSettings>) -> Self {
+        match (self, collage) {
+            (Self::Links, Some(settings)) => Self::Collage(settings),
+            (mode, _) => mode,
+        }
+    }
+}
+
+/// A gallery shown one image at a time, with buttons to page through it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Pager {
+    pub images: Vec<Url>,
+    pub anyone_can_page: bool,
+}
+
+/// Pagination state of a gallery message sent by the bot.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PagerState {
+    pub images: Vec<Url>,
+    pub index: usize,
+
+    /// the only user allowed to page, `None` if anyone is
+    pub owner: Option<UserId>,
+}
+
+impl Pager {
+    pub fn state(&self, poster: UserId) -> PagerState {
+        PagerState {
+            images: self.images.clone(),
+            index: 0,
+            owner: (!self.anyone_can_page).then_some(poster),
+        }
+    }
+}
+
+impl PagerState {
+    pub fn is_allowed(&self, user: UserId) -> bool {
+        self.owner.is_none_or(|owner| owner == user)
+    }
+
+    pub fn advance(&mut self, forward: bool) {
+        let n = self.images.len().max(1);
+
+        self.index = if forward {
+            (self.index + 1) % n
+        } else {
+            (self.index + n - 1) % n
+        };
+    }
+
+    pub fn show(&self, e: CreateEmbed) -> CreateEmbed {
+        let e = e.footer(CreateEmbedFooter::new(format!(
+            "{}/{} images",
+            self.index + 1,
+            self.images.len()
+        )));
+
+        match self.images.get(self.index) {
+            Some(img) => e.image(img.as_str()),
+            None => e,
+        }
+    }
+}
+
+pub fn pager_buttons() -> CreateActionRow {
+    CreateActionRow::Buttons(vec![
+        CreateButton::new(PAGER_PREV_ID).label("◀"),
+        CreateButton::new(PAGER_NEXT_ID).label("▶"),
+    ])
+}
+
+/// A gallery prepared for display while scraping, according to the [`GalleryMode`].
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum Gallery {
+    #[cfg_attr(not(feature = "collage"), allow(dead_code))]
+    Collage(Collage),
+    Pager(Pager),
+}
+
+impl Gallery {
+    /// Prepares a gallery of `images` for display, `None` means the urls should just be listed.
+    pub async fn prepare(mode: &GalleryMode, images: &[Url]) -> Option<Self> {
+        match mode {
+            GalleryMode::Links => None,
+            #[cfg(feature = "collage")]
+            GalleryMode::Collage(settings) => {
+                match super::collage::render_collage(images, settings, "collage.png").await {
+                    Ok(collage) => Some(Gallery::Collage(collage)),
+                    Err(e) => {
+                        tracing::warn!("unable to render collage: {:?}", e);
+                        None
+                    },
+                }
+            },
+            #[cfg(not(feature = "collage"))]
+            GalleryMode::Collage(_) => None,
+            GalleryMode::Pager(settings) => Some(Gallery::Pager(Pager {
+                images: images.to_vec(),
+                anyone_can_page: settings.anyone_can_page,
+            })),
+        }
+    }
+
+    pub fn pager(&self) -> Option<&Pager> {
+        match self {
+            Gallery::Pager(pager) => Some(pager),
+            Gallery::Collage(_) => None,
+        }
+    }
+
+    /// Shows the gallery in `e`, the base embed of the post.
+    pub fn embed(&self, e: CreateEmbed, response: CreateResponse) -> CreateResponse {
+        match self {
+            Gallery::Collage(collage) => response
+                .embed(e.attachment(&collage.attachment.filename).footer(collage.footer()))
+                .add_file(collage.attachment.clone()),
+            Gallery::Pager(pager) => {
+                let state = PagerState { images: pager.images.clone(), index: 0, owner: None };
+                response.embed(state.show(e))
+            },
+        }
+    }
+}
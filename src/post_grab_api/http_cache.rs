@@ -0,0 +1,521 @@
+use super::util::{get_request, spawn_blocking, stable_file_stem, write_atomically};
+use anyhow::Context;
+use reqwest::{
+    header::{HeaderMap, CACHE_CONTROL, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    ResponseBuilderExt, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+    time::{Duration, SystemTime},
+};
+use url::Url;
+
+static HTTP_CACHE: OnceLock<HttpCache> = OnceLock::new();
+
+fn default_max_size_bytes() -> u64 {
+    256 * 1024 * 1024
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HttpCacheSettings {
+    pub directory: PathBuf,
+
+    /// once the cached responses take up more space, the least recently used ones are dropped
+    #[serde(default = "default_max_size_bytes")]
+    pub max_size_bytes: u64,
+}
+
+/// Makes [`super::wget`] cache responses on disk.
+pub fn enable(settings: HttpCacheSettings) -> anyhow::Result<()> {
+    let cache = HttpCache::from_settings(settings)?;
+
+    HTTP_CACHE
+        .set(cache)
+        .map_err(|_| anyhow::anyhow!("http cache is already enabled"))
+}
+
+pub(super) fn get() -> Option<&'static HttpCache> {
+    HTTP_CACHE.get()
+}
+
+/// What is known about a cached response, its body is stored next to it
+#[derive(Serialize, Deserialize, Clone)]
+struct Meta {
+    /// the url the response was served from, after redirects
+    url: Url,
+    headers: Vec<(String, String)>,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    fresh_until: SystemTime,
+    size: u64,
+    last_used: SystemTime,
+}
+
+/// The file format of a cached response's metadata
+#[derive(Serialize, Deserialize)]
+struct StoredMeta {
+    /// the requested url
+    key: String,
+    meta: Meta,
+}
+
+#[derive(Default)]
+struct Index {
+    entries: HashMap<String, Meta>,
+    total_size: u64,
+}
+
+/// How long a response may be used without revalidating it, `None` if it must not be stored.
+fn freshness_lifetime(headers: &HeaderMap) -> Option<Duration> {
+    let mut max_age = Duration::ZERO;
+    let mut no_cache = false;
+
+    let directives = headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|d| d.trim().to_ascii_lowercase());
+
+    for directive in directives {
+        match directive.split_once('=') {
+            None if directive == "no-store" => return None,
+            None if directive == "no-cache" => no_cache = true,
+            Some(("max-age", secs)) => {
+                max_age = Duration::from_secs(secs.trim_matches('"').parse().unwrap_or_default());
+            },
+            _ => (),
+        }
+    }
+
+    Some(if no_cache { Duration::ZERO } else { max_age })
+}
+
+fn header_value(headers: &HeaderMap, name: reqwest::header::HeaderName) -> Option<String> {
+    headers.get(name).and_then(|v| v.to_str().ok()).map(ToOwned::to_owned)
+}
+
+fn build_response<B: Into<reqwest::Body>>(meta: &Meta, body: B) -> anyhow::Result<reqwest::Response> {
+    let builder = meta
+        .headers
+        .iter()
+        .fold(http::Response::builder(), |builder, (name, value)| {
+            builder.header(name, value)
+        });
+
+    Ok(builder.status(StatusCode::OK).url(meta.url.clone()).body(body)?.into())
+}
+
+/// Caches http responses on disk, revalidating them with `ETag` and `Last-Modified` once they are stale.
+pub struct HttpCache {
+    dir: PathBuf,
+    max_size: u64,
+    index: Mutex<Index>,
+}
+
+impl HttpCache {
+    fn from_settings(settings: HttpCacheSettings) -> anyhow::Result<Self> {
+        let dir = settings.directory;
+        std::fs::create_dir_all(&dir).with_context(|| format!("Unable to create {}", dir.display()))?;
+
+        let cache = Self { dir, max_size: settings.max_size_bytes, index: Mutex::new(Index::default()) };
+
+        for dir_entry in
+            std::fs::read_dir(&cache.dir).with_context(|| format!("Unable to read {}", cache.dir.display()))?
+        {
+            let path = dir_entry?.path();
+
+            if path.extension().is_some_and(|ext| ext == "json") {
+                match Self::load_meta(&path) {
+                    Ok(StoredMeta { key, meta }) if cache.body_path(&key).exists() => {
+                        let mut index = cache.index.lock().unwrap();
+                        index.total_size += meta.size;
+                        index.entries.insert(key, meta);
+                    },
+                    Ok(_) => Self::delete_file(&path),
+                    Err(e) => tracing::warn!("ignoring cached response: {:?}", e),
+                }
+            }
+        }
+
+        let evicted = cache.evict(&mut cache.index.lock().unwrap());
+        for path in evicted.iter().flat_map(|key| cache.paths(key)) {
+            Self::delete_file(&path);
+        }
+
+        Ok(cache)
+    }
+
+    fn load_meta(path: &Path) -> anyhow::Result<StoredMeta> {
+        let f = File::open(path).with_context(|| format!("Unable to open {}", path.display()))?;
+        serde_json::from_reader(f).with_context(|| format!("Unable to parse {}", path.display()))
+    }
+
+    fn delete_file(path: &Path) {
+        if let Err(e) = std::fs::remove_file(path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                tracing::warn!("unable to delete cached response {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    fn meta_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", stable_file_stem(key)))
+    }
+
+    fn body_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.body", stable_file_stem(key)))
+    }
+
+    fn paths(&self, key: &str) -> [PathBuf; 2] {
+        [self.meta_path(key), self.body_path(key)]
+    }
+
+    /// Deletes the files of the responses cached for `keys`, which have to be taken out of the index first.
+    async fn delete_files(&self, keys: &[String]) {
+        let paths: Vec<_> = keys.iter().flat_map(|key| self.paths(key)).collect();
+
+        if !paths.is_empty() {
+            let _ = spawn_blocking(move || paths.iter().for_each(|path| Self::delete_file(path))).await;
+        }
+    }
+
+    async fn write_file<F>(path: PathBuf, write: F) -> anyhow::Result<()>
+    where
+        F: FnOnce(&mut BufWriter<File>) -> anyhow::Result<()> + Send + 'static,
+    {
+        spawn_blocking(move || write_atomically(&path, write))
+            .await
+            .context("Writing task failed")?
+    }
+
+    async fn write_meta(&self, key: &str, meta: &Meta) -> anyhow::Result<()> {
+        let stored = StoredMeta { key: key.to_owned(), meta: meta.clone() };
+        Self::write_file(self.meta_path(key), move |f| {
+            serde_json::to_writer(f, &stored).map_err(Into::into)
+        })
+        .await
+    }
+
+    /// Takes `key` out of the index, returning whether it was in there.
+    fn unindex(index: &mut Index, key: &str) -> bool {
+        match index.entries.remove(key) {
+            Some(meta) => {
+                index.total_size -= meta.size;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Takes the least recently used responses out of the index until the cache fits its size cap,
+    /// returning their keys so that their files can be deleted.
+    fn evict(&self, index: &mut Index) -> Vec<String> {
+        let mut evicted = Vec::new();
+
+        while index.total_size > self.max_size {
+            let least_recently_used = index
+                .entries
+                .iter()
+                .min_by_key(|(_, meta)| meta.last_used)
+                .map(|(key, _)| key.clone());
+
+            match least_recently_used {
+                Some(key) => {
+                    Self::unindex(index, &key);
+                    evicted.push(key);
+                },
+                None => break,
+            }
+        }
+
+        evicted
+    }
+
+    /// Forgets the response cached for `key` and deletes its files.
+    async fn forget(&self, key: &str) {
+        Self::unindex(&mut self.index.lock().unwrap(), key);
+        self.delete_files(&[key.to_owned()]).await;
+    }
+
+    fn lookup(&self, key: &str) -> Option<Meta> {
+        let mut index = self.index.lock().unwrap();
+        let meta = index.entries.get_mut(key)?;
+        meta.last_used = SystemTime::now();
+        Some(meta.clone())
+    }
+
+    async fn store<B: AsRef<[u8]> + Send + 'static>(&self, key: &str, meta: Meta, body: B) {
+        if meta.size > self.max_size {
+            return;
+        }
+
+        let written = async {
+            Self::write_file(self.body_path(key), move |f| {
+                f.write_all(body.as_ref()).map_err(Into::into)
+            })
+            .await?;
+            self.write_meta(key, &meta).await
+        };
+
+        if let Err(e) = written.await {
+            tracing::warn!("unable to cache response of {}: {:?}", key, e);
+
+            // a previous response may have been overwritten in part
+            self.forget(key).await;
+            return;
+        }
+
+        let evicted = {
+            let mut index = self.index.lock().unwrap();
+            Self::unindex(&mut index, key);
+            index.total_size += meta.size;
+            index.entries.insert(key.to_owned(), meta);
+            self.evict(&mut index)
+        };
+
+        self.delete_files(&evicted).await;
+    }
+
+    /// Updates the freshness of a cached response after the server confirmed it is still valid.
+    async fn revalidated(&self, key: &str, mut meta: Meta, headers: &HeaderMap, lifetime: Duration) -> Meta {
+        meta.fresh_until = SystemTime::now() + lifetime;
+        meta.etag = header_value(headers, ETAG).or(meta.etag);
+        meta.last_modified = header_value(headers, LAST_MODIFIED).or(meta.last_modified);
+
+        let updated = match self.index.lock().unwrap().entries.get_mut(key) {
+            Some(entry) => {
+                *entry = meta.clone();
+                true
+            },
+            None => false,
+        };
+
+        if updated {
+            if let Err(e) = self.write_meta(key, &meta).await {
+                tracing::warn!("unable to update cached response of {}: {:?}", key, e);
+            }
+        }
+
+        meta
+    }
+
+    async fn cached_response(&self, key: &str, meta: &Meta) -> Option<reqwest::Response> {
+        let body = match tokio::fs::read(self.body_path(key)).await {
+            Ok(body) => body,
+            Err(e) => {
+                tracing::warn!("unable to read cached response of {}: {}", key, e);
+                self.forget(key).await;
+                return None;
+            },
+        };
+
+        build_response(meta, body).ok()
+    }
+
+    /// Fetches `url`, answering from the cache while the response is fresh and revalidating it once stale.
+    pub async fn fetch(&self, url: Url) -> anyhow::Result<reqwest::Response> {
+        let key = url.to_string();
+        let cached = self.lookup(&key);
+
+        if let Some(meta) = &cached {
+            if meta.fresh_until > SystemTime::now() {
+                if let Some(resp) = self.cached_response(&key, meta).await {
+                    return Ok(resp);
+                }
+            }
+        }
+
+        let mut req = get_request(url.clone());
+
+        if let Some(meta) = &cached {
+            if let Some(etag) = &meta.etag {
+                req = req.header(IF_NONE_MATCH, etag);
+            }
+
+            if let Some(last_modified) = &meta.last_modified {
+                req = req.header(IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+
+        let resp = req.send().await?;
+        let lifetime = freshness_lifetime(resp.headers());
+
+        if resp.status() == StatusCode::NOT_MODIFIED {
+            if let Some(meta) = cached {
+                let meta = self
+                    .revalidated(&key, meta, resp.headers(), lifetime.unwrap_or_default())
+                    .await;
+
+                if let Some(resp) = self.cached_response(&key, &meta).await {
+                    return Ok(resp);
+                }
+            }
+
+            // the cached body is gone, ask for a full response
+            return get_request(url).send().await.map_err(Into::into);
+        }
+
+        let etag = header_value(resp.headers(), ETAG);
+        let last_modified = header_value(resp.headers(), LAST_MODIFIED);
+
+        // responses that can neither be reused as they are nor revalidated are not worth storing
+        let Some(lifetime) = lifetime.filter(|l| !l.is_zero() || etag.is_some() || last_modified.is_some()) else {
+            return Ok(resp);
+        };
+
+        if resp.status() != StatusCode::OK {
+            return Ok(resp);
+        }
+
+        let final_url = resp.url().clone();
+
+        let headers = resp
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_owned())))
+            .collect();
+
+        let body = resp.bytes().await?;
+
+        let meta = Meta {
+            url: final_url,
+            headers,
+            etag,
+            last_modified,
+            fresh_until: SystemTime::now() + lifetime,
+            size: body.len() as u64,
+            last_used: SystemTime::now(),
+        };
+
+        self.store(&key, meta.clone(), body.clone()).await;
+        build_response(&meta, body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::post_grab_api::{http_response, serve};
+    use reqwest::header::HeaderValue;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    fn lifetime(cache_control: &[&str]) -> Option<Duration> {
+        let mut headers = HeaderMap::new();
+        for value in cache_control {
+            headers.append(CACHE_CONTROL, HeaderValue::from_str(value).unwrap());
+        }
+
+        freshness_lifetime(&headers)
+    }
+
+    fn cache(dir: &Path, max_size_bytes: u64) -> HttpCache {
+        HttpCache::from_settings(HttpCacheSettings { directory: dir.to_owned(), max_size_bytes }).unwrap()
+    }
+
+    async fn body(cache: &HttpCache, url: Url) -> String {
+        cache.fetch(url).await.unwrap().text().await.unwrap()
+    }
+
+    #[test]
+    fn freshness() {
+        assert_eq!(lifetime(&[]), Some(Duration::ZERO));
+        assert_eq!(lifetime(&["max-age=60"]), Some(Duration::from_secs(60)));
+        assert_eq!(lifetime(&["public, Max-Age=\"30\""]), Some(Duration::from_secs(30)));
+        assert_eq!(lifetime(&["public", "max-age=30"]), Some(Duration::from_secs(30)));
+        assert_eq!(lifetime(&["max-age=60, no-cache"]), Some(Duration::ZERO));
+        assert_eq!(lifetime(&["max-age=60", "no-store"]), None);
+    }
+
+    #[tokio::test]
+    async fn revalidation() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache(dir.path(), 1024);
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        let url = serve({
+            let requests = requests.clone();
+
+            move |request| {
+                requests.fetch_add(1, Ordering::SeqCst);
+
+                if request.to_ascii_lowercase().contains("if-none-match: \"v1\"") {
+                    http_response("304 Not Modified\r\nCache-Control: max-age=60", b"")
+                } else {
+                    http_response("200 OK\r\nETag: \"v1\"\r\nCache-Control: no-cache", b"body")
+                }
+            }
+        });
+
+        assert_eq!(body(&cache, url.clone()).await, "body");
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        // stale right away, but the server confirms the cached body is still valid
+        assert_eq!(body(&cache, url.clone()).await, "body");
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        // and now it is fresh for a while
+        assert_eq!(body(&cache, url).await, "body");
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn eviction() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache(dir.path(), 10);
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        let url = serve({
+            let requests = requests.clone();
+
+            move |request| {
+                requests.fetch_add(1, Ordering::SeqCst);
+
+                let body: &[u8] = if request.starts_with("GET /large") {
+                    b"far too large"
+                } else {
+                    b"small"
+                };
+                http_response("200 OK\r\nCache-Control: max-age=60", body)
+            }
+        });
+
+        let (a, b, large) = (
+            url.join("a").unwrap(),
+            url.join("b").unwrap(),
+            url.join("large").unwrap(),
+        );
+
+        body(&cache, a.clone()).await;
+        body(&cache, b.clone()).await;
+        body(&cache, a.clone()).await;
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        // responses larger than the whole cache are not stored and push nothing out
+        body(&cache, large.clone()).await;
+        body(&cache, large).await;
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+
+        // b is the least recently used response
+        body(&cache, url.join("c").unwrap()).await;
+        assert_eq!(requests.load(Ordering::SeqCst), 5);
+        assert!(!cache.body_path(b.as_str()).exists());
+
+        body(&cache, a.clone()).await;
+        assert_eq!(requests.load(Ordering::SeqCst), 5);
+
+        // what is left survives a restart
+        let cache = self::cache(dir.path(), 10);
+        assert_eq!(cache.index.lock().unwrap().total_size, 10);
+        assert_eq!(body(&cache, a).await, "small");
+        assert_eq!(requests.load(Ordering::SeqCst), 5);
+    }
+}
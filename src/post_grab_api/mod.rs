@@ -1,12 +1,26 @@
+pub mod blur;
+pub mod collage;
+pub mod gallery;
+pub mod http_cache;
 pub mod ninegag;
 pub mod reddit;
+pub mod scrape_error;
+pub mod serde_attachment;
 pub mod svg;
+pub mod transcode;
 pub mod twitter;
 pub mod util;
 
+use blur::BlurSettings;
+use gallery::Pager;
+pub use scrape_error::ScrapeError;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serenity::{
     async_trait,
-    builder::{CreateAttachment, CreateEmbed, CreateInteractionResponseMessage, CreateMessage},
+    builder::{
+        CreateActionRow, CreateAttachment, CreateEmbed, CreateInteractionResponseMessage, CreateMessage,
+        EditInteractionResponse, EditMessage,
+    },
     model::user::User,
 };
 use thiserror::Error;
@@ -21,21 +35,109 @@ pub enum Error {
     #[error("no api available")]
     NoApiAvailable,
 
-    #[error("unable to fetch post: {0}")]
-    PostFetchFailed(#[from] anyhow::Error),
+    #[error("you are not allowed to embed posts from {0}")]
+    ModuleNotAllowed(&'static str),
+
+    #[error("too many embeds, try again in {} seconds", .0.as_secs().max(1))]
+    RateLimited(std::time::Duration),
+
+    #[error("too many posts from {0} are being embedded right now, try again later")]
+    QueueFull(&'static str),
+
+    #[error("fetching the post from {0} took too long")]
+    TimedOut(&'static str),
+
+    #[error("{0} is not working right now, try again later")]
+    ModuleUnavailable(&'static str),
+
+    #[error("unable to fetch post ({kind}): {source:#}")]
+    PostFetchFailed { kind: ScrapeError, source: anyhow::Error },
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(source: anyhow::Error) -> Self {
+        Self::PostFetchFailed { kind: ScrapeError::classify(&source), source }
+    }
+}
+
+impl Error {
+    /// What went wrong while scraping, if the module was called at all.
+    pub fn scrape_error(&self) -> Option<ScrapeError> {
+        match self {
+            Self::PostFetchFailed { kind, .. } => Some(*kind),
+            Self::TimedOut(_) => Some(ScrapeError::Timeout),
+            _ => None,
+        }
+    }
+
+    /// A message explaining the error to users, in their language if available.
+    /// `locale` is a discord locale like `en-US` or `de`.
+    pub fn user_message(&self, locale: &str) -> String {
+        match (locale.split('-').next().unwrap_or_default(), self) {
+            (_, Self::PostFetchFailed { kind, .. }) => kind.user_message(locale).to_owned(),
+            (_, Self::TimedOut(_)) => ScrapeError::Timeout.user_message(locale).to_owned(),
+
+            ("de", Self::NotSupposedToEmbed(_)) => "Dieser Post wird nicht eingebettet.".to_owned(),
+            ("de", Self::NoApiAvailable) => "Posts von dieser Seite können nicht eingebettet werden.".to_owned(),
+            ("de", Self::ModuleNotAllowed(module)) => format!("Du darfst hier keine Posts von {module} einbetten."),
+            ("de", Self::RateLimited(wait)) => {
+                format!(
+                    "Zu viele Einbettungen, versuch es in {} Sekunden nochmal.",
+                    wait.as_secs().max(1)
+                )
+            },
+            ("de", Self::QueueFull(module)) => {
+                format!("Gerade werden zu viele Posts von {module} eingebettet, versuch es später nochmal.")
+            },
+            ("de", Self::ModuleUnavailable(module)) => {
+                format!("{module} funktioniert gerade nicht, versuch es später nochmal.")
+            },
+
+            (_, Self::NotSupposedToEmbed(_)) => "This post is not supposed to be embedded.".to_owned(),
+            (_, Self::NoApiAvailable) => "Posts from this site cannot be embedded.".to_owned(),
+            (_, Self::ModuleNotAllowed(module)) => format!("You are not allowed to embed posts from {module} here."),
+            (_, Self::RateLimited(wait)) => {
+                format!("Too many embeds, try again in {} seconds.", wait.as_secs().max(1))
+            },
+            (_, Self::QueueFull(module)) => {
+                format!("Too many posts from {module} are being embedded right now, try again later.")
+            },
+            (_, Self::ModuleUnavailable(module)) => format!("{module} is not working right now, try again later."),
+        }
+    }
+}
+
+/// What kind of content a post is, regardless of the site it comes from
+#[derive(Serialize, Deserialize, Eq, PartialEq, Hash, Copy, Clone, Debug)]
+pub enum ContentType {
+    Text,
+    Image,
+    Gallery,
+    Video,
 }
 
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default)]
 pub struct EmbedOptions {
     pub comment: Option<String>,
     pub ignore_nsfw: bool,
     pub ignore_spoiler: bool,
+
+    /// shown in place of the content of the post while it is hidden
+    pub blurred_preview: Option<CreateAttachment>,
 }
 
 pub enum CreateResponse {
     #[cfg(feature = "implicit-auto-embed")]
     Message(CreateMessage),
+
+    /// replaces an embed sent earlier, which has to be cleared first
+    #[cfg(feature = "implicit-auto-embed")]
+    Edit(EditMessage),
+
     Interaction(CreateInteractionResponseMessage),
+
+    /// fills in a deferred interaction response
+    EditInteraction(EditInteractionResponse),
 }
 
 impl CreateResponse {
@@ -47,6 +149,14 @@ impl CreateResponse {
         }
     }
 
+    #[cfg(feature = "implicit-auto-embed")]
+    pub fn into_edit(self) -> EditMessage {
+        match self {
+            Self::Edit(m) => m,
+            _ => unreachable!(),
+        }
+    }
+
     pub fn into_interaction(self) -> CreateInteractionResponseMessage {
         match self {
             Self::Interaction(m) => m,
@@ -54,11 +164,21 @@ impl CreateResponse {
         }
     }
 
+    pub fn into_edit_interaction(self) -> EditInteractionResponse {
+        match self {
+            Self::EditInteraction(m) => m,
+            _ => unreachable!(),
+        }
+    }
+
     pub fn content(self, s: impl Into<String>) -> Self {
         match self {
             #[cfg(feature = "implicit-auto-embed")]
             CreateResponse::Message(response) => CreateResponse::Message(response.content(s)),
+            #[cfg(feature = "implicit-auto-embed")]
+            CreateResponse::Edit(response) => CreateResponse::Edit(response.content(s)),
             CreateResponse::Interaction(response) => CreateResponse::Interaction(response.content(s)),
+            CreateResponse::EditInteraction(response) => CreateResponse::EditInteraction(response.content(s)),
         }
     }
 
@@ -66,7 +186,10 @@ impl CreateResponse {
         match self {
             #[cfg(feature = "implicit-auto-embed")]
             CreateResponse::Message(response) => CreateResponse::Message(response.embed(e)),
+            #[cfg(feature = "implicit-auto-embed")]
+            CreateResponse::Edit(response) => CreateResponse::Edit(response.embed(e)),
             CreateResponse::Interaction(response) => CreateResponse::Interaction(response.embed(e)),
+            CreateResponse::EditInteraction(response) => CreateResponse::EditInteraction(response.embed(e)),
         }
     }
 
@@ -74,7 +197,21 @@ impl CreateResponse {
         match self {
             #[cfg(feature = "implicit-auto-embed")]
             CreateResponse::Message(response) => CreateResponse::Message(response.add_file(a)),
+            #[cfg(feature = "implicit-auto-embed")]
+            CreateResponse::Edit(response) => CreateResponse::Edit(response.new_attachment(a)),
             CreateResponse::Interaction(response) => CreateResponse::Interaction(response.add_file(a)),
+            CreateResponse::EditInteraction(response) => CreateResponse::EditInteraction(response.new_attachment(a)),
+        }
+    }
+
+    pub fn components(self, c: Vec<CreateActionRow>) -> Self {
+        match self {
+            #[cfg(feature = "implicit-auto-embed")]
+            CreateResponse::Message(response) => CreateResponse::Message(response.components(c)),
+            #[cfg(feature = "implicit-auto-embed")]
+            CreateResponse::Edit(response) => CreateResponse::Edit(response.components(c)),
+            CreateResponse::Interaction(response) => CreateResponse::Interaction(response.components(c)),
+            CreateResponse::EditInteraction(response) => CreateResponse::EditInteraction(response.components(c)),
         }
     }
 }
@@ -83,39 +220,129 @@ impl CreateResponse {
 pub trait PostScraper {
     type Output: Post;
 
+    /// The name of the module, as used in the config file.
+    fn name(&self) -> &'static str;
+
     fn is_suitable(&self, url: &Url) -> bool;
+
+    /// Whether this scraper wants to handle urls serving `content_type`.
+    /// Only consulted if no scraper claimed the url by [`PostScraper::is_suitable`]
+    /// and content type probing is enabled.
+    fn is_suitable_content_type(&self, _content_type: &str) -> bool {
+        false
+    }
+
     fn should_embed(&self, post: &Self::Output) -> bool;
 
+    /// How previews of hidden posts are blurred, `None` if they are not shown at all.
+    #[cfg_attr(not(feature = "blur"), allow(dead_code))]
+    fn blur_settings(&self) -> Option<&BlurSettings> {
+        None
+    }
+
     async fn get_post(&self, url: Url) -> anyhow::Result<Self::Output>;
+
+    /// Does the costly part of preparing `post` for its embed, like rendering galleries.
+    /// Only called once [`PostScraper::should_embed`] accepted the post.
+    async fn finish_post(&self, _post: &mut Self::Output) {}
+}
+
+/// Turns a post into json, restored by [`DynPostScraper::decode_post`].
+pub trait EncodePost {
+    fn encode(&self) -> serde_json::Result<serde_json::Value>;
+}
+
+impl<T: Serialize> EncodePost for T {
+    fn encode(&self) -> serde_json::Result<serde_json::Value> {
+        serde_json::to_value(self)
+    }
 }
 
-pub trait Post: std::fmt::Debug + Send + Sync {
+pub trait Post: std::fmt::Debug + Send + Sync + EncodePost {
     fn create_embed(&self, u: &User, opts: &EmbedOptions, response: CreateResponse) -> CreateResponse;
+
+    fn content_type(&self) -> ContentType;
+
+    /// The url identifying the post, whichever link led to it.
+    fn canonical_url(&self) -> Url;
+
+    /// The title of the embed, formatted as markdown.
+    fn title(&self) -> Option<String> {
+        None
+    }
+
+    /// The gallery shown by the embed, if it is paged through with buttons.
+    fn pager(&self) -> Option<&Pager> {
+        None
+    }
+
+    fn is_nsfw(&self) -> bool {
+        false
+    }
+
+    /// An image that gives an idea of what the post is about, blurred in its place while it is hidden.
+    #[cfg_attr(not(feature = "blur"), allow(dead_code))]
+    fn preview_image(&self) -> Option<&Url> {
+        None
+    }
+
+    fn is_spoiler(&self) -> bool {
+        false
+    }
+
+    /// Whether the embed created with `opts` hides the content of the post.
+    fn is_hidden(&self, opts: &EmbedOptions) -> bool {
+        (self.is_nsfw() && !opts.ignore_nsfw) || (self.is_spoiler() && !opts.ignore_spoiler)
+    }
 }
 
 #[async_trait]
 pub trait DynPostScraper {
+    fn name(&self) -> &'static str;
     fn is_suitable(&self, url: &Url) -> bool;
+    fn is_suitable_content_type(&self, content_type: &str) -> bool;
+    #[cfg_attr(not(feature = "blur"), allow(dead_code))]
+    fn blur_settings(&self) -> Option<&BlurSettings>;
     async fn get_dyn_post(&self, url: Url) -> Result<Box<dyn Post>, Error>;
+
+    /// Restores a post of this module encoded by [`EncodePost::encode`].
+    fn decode_post(&self, value: serde_json::Value) -> serde_json::Result<Box<dyn Post>>;
 }
 
 #[async_trait]
 impl<PS, O> DynPostScraper for PS
 where
     PS: PostScraper<Output = O> + Sync,
-    O: Post + 'static,
+    O: Post + DeserializeOwned + 'static,
 {
+    fn name(&self) -> &'static str {
+        PostScraper::name(self)
+    }
+
     fn is_suitable(&self, url: &Url) -> bool {
         PostScraper::is_suitable(self, url)
     }
 
+    fn is_suitable_content_type(&self, content_type: &str) -> bool {
+        PostScraper::is_suitable_content_type(self, content_type)
+    }
+
+    fn blur_settings(&self) -> Option<&BlurSettings> {
+        PostScraper::blur_settings(self)
+    }
+
     async fn get_dyn_post(&self, url: Url) -> Result<Box<dyn Post>, Error> {
-        let p = self.get_post(url).await?;
+        let mut p = self.get_post(url).await?;
 
         if self.should_embed(&p) {
+            self.finish_post(&mut p).await;
             Ok(Box::new(p))
         } else {
             Err(Error::NotSupposedToEmbed(Box::new(p)))
         }
     }
+
+    fn decode_post(&self, value: serde_json::Value) -> serde_json::Result<Box<dyn Post>> {
+        Ok(Box::new(serde_json::from_value::<O>(value)?))
+    }
 }
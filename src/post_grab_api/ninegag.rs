@@ -1,9 +1,10 @@
 #![cfg(feature = "ninegag")]
 
 use super::{
-    escape_markdown, include_author_comment, limit_len, wget, CreateResponse, EmbedOptions, Post as PostTrait,
-    PostScraper, EMBED_TITLE_MAX_LEN,
+    blur::BlurSettings, embed_with_attachment, escape_markdown, include_author_comment, limit_len, wget, ContentType,
+    CreateResponse, EmbedOptions, Post as PostTrait, PostScraper, ScrapeError, EMBED_TITLE_MAX_LEN,
 };
+use anyhow::Context;
 use json_nav::json_nav;
 use reqwest::IntoUrl;
 use serde::{Deserialize, Serialize};
@@ -12,7 +13,7 @@ use std::collections::HashSet;
 use url::Url;
 
 async fn wget_html<U: IntoUrl>(url: U) -> anyhow::Result<scraper::Html> {
-    let resp = wget(url).await?;
+    let resp = wget(url).await?.error_for_status()?;
     Ok(scraper::Html::parse_document(&resp.text().await?))
 }
 
@@ -23,27 +24,43 @@ fn fmt_title(p: &Post) -> String {
     format!("'{}' - **9GAG**", title)
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
 pub enum NineGagPostType {
     Image,
     Video,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Post {
-    src: String,
+    src: Url,
     title: String,
     embed_url: String,
     post_type: NineGagPostType,
+    nsfw: bool,
+
+    preview: Option<Url>,
 }
 
 impl PostTrait for Post {
     fn create_embed(&self, u: &User, opts: &EmbedOptions, response: CreateResponse) -> CreateResponse {
+        if self.nsfw && !opts.ignore_nsfw {
+            let mut e = CreateEmbed::new()
+                .title(&self.title)
+                .description("Warning NSFW: Click to view content")
+                .url(self.src.as_str());
+
+            if let Some(comment) = &opts.comment {
+                e = include_author_comment(e, u, comment);
+            }
+
+            return embed_with_attachment(response, e, opts.blurred_preview.as_ref());
+        }
+
         match self.post_type {
             NineGagPostType::Image => response.embed({
                 let mut e = CreateEmbed::new()
                     .title(&self.title)
-                    .url(&self.src)
+                    .url(self.src.as_str())
                     .image(&self.embed_url);
 
                 if let Some(comment) = &opts.comment {
@@ -70,6 +87,31 @@ impl PostTrait for Post {
             },
         }
     }
+
+    fn content_type(&self) -> ContentType {
+        match self.post_type {
+            NineGagPostType::Image => ContentType::Image,
+            NineGagPostType::Video => ContentType::Video,
+        }
+    }
+
+    fn canonical_url(&self) -> Url {
+        let mut url = self.src.clone();
+        url.set_query(None);
+        url
+    }
+
+    fn title(&self) -> Option<String> {
+        Some(fmt_title(self))
+    }
+
+    fn is_nsfw(&self) -> bool {
+        self.nsfw
+    }
+
+    fn preview_image(&self) -> Option<&Url> {
+        self.preview.as_ref()
+    }
 }
 
 #[derive(Serialize, Deserialize, Eq, PartialEq, Hash, Debug)]
@@ -81,6 +123,9 @@ pub enum SettingsPostType {
 #[derive(Deserialize, Serialize, Debug)]
 pub struct ApiSettings {
     pub embed_set: HashSet<SettingsPostType>,
+
+    /// show blurred previews of nsfw posts
+    pub blur: Option<BlurSettings>,
 }
 
 pub struct Api {
@@ -91,26 +136,8 @@ impl Api {
     pub fn from_settings(settings: ApiSettings) -> Self {
         Self { settings }
     }
-}
-
-#[async_trait]
-impl PostScraper for Api {
-    type Output = Post;
-
-    fn is_suitable(&self, url: &Url) -> bool {
-        url.domain() == Some("9gag.com")
-    }
-
-    fn should_embed(&self, post: &Self::Output) -> bool {
-        self.settings.embed_set.contains(&match post.post_type {
-            NineGagPostType::Video => SettingsPostType::Video,
-            NineGagPostType::Image => SettingsPostType::Image,
-        })
-    }
-
-    async fn get_post(&self, url: Url) -> anyhow::Result<Self::Output> {
-        let html = wget_html(url.clone()).await?;
 
+    fn analyze_post(url: Url, html: &scraper::Html) -> anyhow::Result<Post> {
         let title: String = {
             let title_selector = scraper::Selector::parse("title").unwrap();
             html.select(&title_selector)
@@ -131,7 +158,11 @@ impl PostScraper for Api {
                 .collect::<String>()
                 .replace('\\', "");
 
-            serde_json::from_str(&script_text[29..(script_text.len() - 3)])?
+            let json = script_text
+                .get(29..script_text.len().saturating_sub(3))
+                .ok_or_else(|| anyhow::anyhow!("json too short"))?;
+
+            serde_json::from_str(json)?
         };
 
         let post_json = json_nav! { build_json => "data" => "post"; as object }?;
@@ -159,11 +190,85 @@ impl PostScraper for Api {
             ),
         };
 
+        let nsfw = json_nav! { post_json => "nsfw" }
+            .map(|v| v.as_u64() == Some(1) || v.as_bool() == Some(true))
+            .unwrap_or_default();
+
+        // videos come with a still image as well
+        let preview = json_nav! { post_json => "images" => "image700" => "url"; as str }
+            .ok()
+            .and_then(|u| Url::parse(u).ok());
+
         Ok(Post {
-            src: url.to_string(),
-            title: title[0..(title.len() - 7)].to_string(), // remove ' - 9GAG' from end
+            src: url,
+            title: title.strip_suffix(" - 9GAG").unwrap_or(&title).to_string(),
             embed_url,
             post_type,
+            nsfw,
+            preview,
+        })
+    }
+}
+
+#[async_trait]
+impl PostScraper for Api {
+    type Output = Post;
+
+    fn name(&self) -> &'static str {
+        "ninegag"
+    }
+
+    fn is_suitable(&self, url: &Url) -> bool {
+        url.domain() == Some("9gag.com")
+    }
+
+    fn blur_settings(&self) -> Option<&BlurSettings> {
+        self.settings.blur.as_ref()
+    }
+
+    fn should_embed(&self, post: &Self::Output) -> bool {
+        self.settings.embed_set.contains(&match post.post_type {
+            NineGagPostType::Video => SettingsPostType::Video,
+            NineGagPostType::Image => SettingsPostType::Image,
         })
     }
+
+    async fn get_post(&self, url: Url) -> anyhow::Result<Self::Output> {
+        Ok(Self::analyze_post(url.clone(), &wget_html(url).await?).context(ScrapeError::LayoutChanged)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn analyze(html: &str) -> anyhow::Result<Post> {
+        let url = Url::parse("https://9gag.com/gag/a1b2c3").unwrap();
+        Api::analyze_post(url, &scraper::Html::parse_document(html))
+    }
+
+    #[test]
+    fn malformed_page() {
+        let pages = [
+            "",
+            "<title>Post - 9GAG</title>",
+            "<title>Post - 9GAG</title><script>JSON.parse</script>",
+            r#"<title>Post - 9GAG</title><script>window._config = JSON.parse("{not json}");</script>"#,
+            r#"<title>Post - 9GAG</title><script>window._config = JSON.parse("{\"data\": {}}");</script>"#,
+        ];
+
+        for page in pages {
+            assert!(analyze(page).is_err(), "{page}");
+        }
+    }
+
+    #[test]
+    fn image_post() {
+        let page = r#"<title>A</title><script>window._config = JSON.parse("{\"data\": {\"post\": {\"type\": \"Photo\", \"images\": {\"image700\": {\"url\": \"https://img-9gag-fun.9cache.com/photo/a1b2c3_700b.jpg\"}}}}}");</script>"#;
+        let post = analyze(page).unwrap();
+
+        assert_eq!(post.title, "A");
+        assert!(matches!(post.post_type, NineGagPostType::Image));
+        assert_eq!(post.embed_url, "https://img-9gag-fun.9cache.com/photo/a1b2c3_700b.jpg");
+    }
 }
@@ -3,10 +3,15 @@
 pub mod module_settings;
 
 use super::{
-    escape_markdown, include_author_comment, limit_descr_len, limit_len, url_path_ends_with,
-    url_path_ends_with_image_extension, wget, wget_json, CreateResponse, EmbedOptions, Post as PostTrait, PostScraper,
-    EMBED_TITLE_MAX_LEN,
+    blur::BlurSettings,
+    collage::CollageSettings,
+    embed_with_attachment, escape_markdown,
+    gallery::{Gallery, GalleryMode, Pager},
+    include_author_comment, limit_descr_len, limit_len, serde_attachment, url_path_ends_with,
+    url_path_ends_with_image_extension, wget, wget_json, ContentType, CreateResponse, EmbedOptions, Post as PostTrait,
+    PostScraper, ScrapeError, EMBED_TITLE_MAX_LEN,
 };
+use anyhow::Context;
 use itertools::Itertools;
 use json_nav::json_nav;
 use reqwest::IntoUrl;
@@ -14,7 +19,7 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use serenity::{
     async_trait,
-    builder::{CreateEmbed, CreateEmbedAuthor},
+    builder::{CreateAttachment, CreateEmbed, CreateEmbedAuthor},
     model::user::User,
 };
 use std::convert::TryInto;
@@ -95,19 +100,19 @@ fn unescape_html(html: &str) -> String {
         .replace("&quot;", "\"")
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub enum PostOrigin {
     JustSubreddit(String),
     Crossposted { from: String, to: String },
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct Comment {
     author: String,
     body: String,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct PostCommonData {
     src: Url,
     subreddit: PostOrigin,
@@ -117,9 +122,10 @@ pub struct PostCommonData {
     nsfw: bool,
     spoiler: bool,
     comment: Option<Comment>,
+    preview: Option<Url>,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub enum PostSpecializedData {
     Text,
     Gallery { img_urls: Vec<Url> },
@@ -127,10 +133,17 @@ pub enum PostSpecializedData {
     Video { video_url: Url },
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Post {
     common: PostCommonData,
     specialized: PostSpecializedData,
+
+    /// PNG version of the image of an image post whose format discord cannot display
+    #[serde(with = "serde_attachment::option")]
+    transcoded_image: Option<CreateAttachment>,
+
+    /// the images of a gallery post, prepared for display
+    gallery: Option<Gallery>,
 }
 
 fn manual_embed(author: &str, post: &PostCommonData, embed_urls: &[Url], discord_comment: Option<&str>) -> String {
@@ -167,7 +180,7 @@ fn manual_embed(author: &str, post: &PostCommonData, embed_urls: &[Url], discord
 impl PostTrait for Post {
     fn create_embed(&self, u: &User, opts: &EmbedOptions, response: CreateResponse) -> CreateResponse {
         if self.common.nsfw && !opts.ignore_nsfw {
-            response.embed({
+            let e = {
                 let mut e = CreateEmbed::new()
                     .title(fmt_title(&self.common))
                     .description("Warning NSFW: Click to view content")
@@ -179,9 +192,11 @@ impl PostTrait for Post {
                 }
 
                 e
-            })
+            };
+
+            embed_with_attachment(response, e, opts.blurred_preview.as_ref())
         } else if self.common.spoiler && !opts.ignore_spoiler {
-            response.embed({
+            let e = {
                 let mut e = CreateEmbed::new()
                     .title(fmt_title(&self.common))
                     .description("Spoiler: Click to view content")
@@ -197,17 +212,30 @@ impl PostTrait for Post {
                 }
 
                 e
-            })
+            };
+
+            embed_with_attachment(response, e, opts.blurred_preview.as_ref())
         } else {
             match &self.specialized {
                 PostSpecializedData::Text => {
                     response.embed(base_embed(CreateEmbed::new(), u, opts.comment.as_deref(), &self.common))
                 },
-                PostSpecializedData::Image { img_url } => response.embed(
-                    base_embed(CreateEmbed::new(), u, opts.comment.as_deref(), &self.common).image(img_url.as_str()),
-                ),
-                PostSpecializedData::Gallery { img_urls } => {
-                    response.content(manual_embed(&u.name, &self.common, img_urls, opts.comment.as_deref()))
+                PostSpecializedData::Image { img_url } => {
+                    let e = base_embed(CreateEmbed::new(), u, opts.comment.as_deref(), &self.common);
+
+                    match &self.transcoded_image {
+                        Some(attachment) => response
+                            .embed(e.attachment(&attachment.filename))
+                            .add_file(attachment.clone()),
+                        None => response.embed(e.image(img_url.as_str())),
+                    }
+                },
+                PostSpecializedData::Gallery { img_urls } => match &self.gallery {
+                    Some(gallery) => gallery.embed(
+                        base_embed(CreateEmbed::new(), u, opts.comment.as_deref(), &self.common),
+                        response,
+                    ),
+                    None => response.content(manual_embed(&u.name, &self.common, img_urls, opts.comment.as_deref())),
                 },
                 PostSpecializedData::Video { video_url } => response.content(manual_embed(
                     &u.name,
@@ -218,11 +246,60 @@ impl PostTrait for Post {
             }
         }
     }
+
+    fn content_type(&self) -> ContentType {
+        match &self.specialized {
+            PostSpecializedData::Text => ContentType::Text,
+            PostSpecializedData::Gallery { .. } => ContentType::Gallery,
+            PostSpecializedData::Image { .. } => ContentType::Image,
+            PostSpecializedData::Video { .. } => ContentType::Video,
+        }
+    }
+
+    /// Already resolved through [`find_canonical_post_url`] while scraping.
+    fn canonical_url(&self) -> Url {
+        self.common.src.clone()
+    }
+
+    fn title(&self) -> Option<String> {
+        Some(fmt_title(&self.common))
+    }
+
+    fn pager(&self) -> Option<&Pager> {
+        self.gallery.as_ref().and_then(Gallery::pager)
+    }
+
+    fn is_nsfw(&self) -> bool {
+        self.common.nsfw
+    }
+
+    fn is_spoiler(&self) -> bool {
+        self.common.spoiler
+    }
+
+    fn preview_image(&self) -> Option<&Url> {
+        match &self.specialized {
+            PostSpecializedData::Image { img_url } => Some(img_url),
+            PostSpecializedData::Gallery { img_urls } => img_urls.first(),
+            PostSpecializedData::Text | PostSpecializedData::Video { .. } => self.common.preview.as_ref(),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ApiSettings {
     pub embed_set: module_settings::EmbedSet,
+
+    /// how to display gallery posts
+    #[serde(default)]
+    pub gallery: GalleryMode,
+
+    /// superseded by `gallery`, still read so that older configs keep working
+    #[serde(default, skip_serializing)]
+    pub collage: Option<CollageSettings>,
+
+    /// show blurred previews of nsfw and spoiler posts
+    pub blur: Option<BlurSettings>,
 }
 
 pub struct Api {
@@ -230,7 +307,8 @@ pub struct Api {
 }
 
 impl Api {
-    pub fn from_settings(settings: ApiSettings) -> Self {
+    pub fn from_settings(mut settings: ApiSettings) -> Self {
+        settings.gallery = std::mem::take(&mut settings.gallery).or_legacy_collage(settings.collage.take());
         Self { settings }
     }
 
@@ -240,6 +318,10 @@ impl Api {
             as object
         }?;
 
+        if json_nav! { top_level_post => "removed_by_category"; as str }.is_ok() {
+            anyhow::bail!(ScrapeError::NotFound);
+        }
+
         let title = json_nav! { top_level_post => "title"; as str }?.to_string();
 
         let subreddit = json_nav! { top_level_post => "subreddit"; as str }?.to_string();
@@ -261,6 +343,10 @@ impl Api {
 
         let spoiler = json_nav! { post_json => "spoiler"; as bool }.unwrap_or_default();
 
+        let preview = json_nav! { post_json => "preview" => "images" => 0 => "source" => "url"; as str }
+            .ok()
+            .and_then(|u| Url::parse(&unescape_url(u)).ok());
+
         let comment = {
             let comment_json = json_nav! {
                 json => 1 => "data" => "children" => 0 => "data"
@@ -289,6 +375,7 @@ impl Api {
             text,
             flair,
             comment,
+            preview,
         };
 
         // embed_url can be "default" when the original post (referenced by crosspost) is deleted
@@ -339,7 +426,12 @@ impl Api {
             },
         };
 
-        Ok(Post { common: common_data, specialized: specialized_data })
+        Ok(Post {
+            common: common_data,
+            specialized: specialized_data,
+            transcoded_image: None,
+            gallery: None,
+        })
     }
 
     async fn scrape_post(&self, url: Url) -> anyhow::Result<Post> {
@@ -353,7 +445,7 @@ impl Api {
             (u, wget_json(get_url).await?)
         };
 
-        Self::analyze_post(url, &json)
+        Self::analyze_post(url, &json).context(ScrapeError::LayoutChanged)
     }
 }
 
@@ -361,10 +453,18 @@ impl Api {
 impl PostScraper for Api {
     type Output = Post;
 
+    fn name(&self) -> &'static str {
+        "reddit"
+    }
+
     fn is_suitable(&self, url: &Url) -> bool {
         ["reddit.com", "www.reddit.com"].map(Some).contains(&url.domain())
     }
 
+    fn blur_settings(&self) -> Option<&BlurSettings> {
+        self.settings.blur.as_ref()
+    }
+
     fn should_embed(&self, post: &Self::Output) -> bool {
         let content_type = match &post.specialized {
             PostSpecializedData::Text => module_settings::ContentType::Text,
@@ -392,6 +492,22 @@ impl PostScraper for Api {
     async fn get_post(&self, url: Url) -> anyhow::Result<Self::Output> {
         Ok(self.scrape_post(url).await?)
     }
+
+    async fn finish_post(&self, post: &mut Self::Output) {
+        #[cfg(feature = "transcode")]
+        if let PostSpecializedData::Image { img_url } = &post.specialized {
+            if super::transcode::needs_transcoding(img_url) {
+                match super::transcode::transcode_to_png(img_url.clone(), "image.png").await {
+                    Ok(attachment) => post.transcoded_image = Some(attachment),
+                    Err(e) => tracing::warn!("unable to transcode {}: {:?}", img_url, e),
+                }
+            }
+        }
+
+        if let PostSpecializedData::Gallery { img_urls } = &post.specialized {
+            post.gallery = Gallery::prepare(&self.settings.gallery, img_urls).await;
+        }
+    }
 }
 
 #[cfg(test)]
@@ -417,10 +533,13 @@ mod tests {
                 nsfw: false,
                 spoiler: false,
                 comment: None,
+                preview: Some(Url::from_str("https://preview.redd.it/bsp1l1vynla71.jpg?auto=webp&s=be94c70f008e63eded33e746321661ab5ca0cb25").unwrap()),
             },
             specialized: PostSpecializedData::Image {
                 img_url: Url::from_str("https://i.redd.it/bsp1l1vynla71.jpg").unwrap(),
             },
+            transcoded_image: None,
+            gallery: None,
         };
 
         assert_eq!(expected, post);
@@ -447,10 +566,13 @@ mod tests {
                 nsfw: false,
                 spoiler: false,
                 comment: None,
+                preview: Some(Url::from_str("https://external-preview.redd.it/FZh_q4TebegkmrGLx7L1xtJ8xdPjFvkMS0E0636pXGM.png?format=pjpg&auto=webp&s=05955dcc2567cf2c87651e78e65252d4c5845caa").unwrap()),
             },
             specialized: PostSpecializedData::Video {
                 video_url: Url::from_str("https://v.redd.it/jx4ua6lirla71/DASH_1080.mp4?source=fallback").unwrap(),
             },
+            transcoded_image: None,
+            gallery: None,
         };
 
         assert_eq!(expected, post);
@@ -475,6 +597,7 @@ mod tests {
                 nsfw: false,
                 spoiler: false,
                 comment: None,
+                preview: None,
             },
             specialized: PostSpecializedData::Gallery {
                 img_urls: vec![
@@ -482,8 +605,42 @@ mod tests {
                     Url::from_str("https://preview.redd.it/wrro81ytsha71.jpg?width=4000&format=pjpg&auto=webp&s=5f1a86f3783d7ae290f733083b2af4397332c1be").unwrap(),
                 ],
             },
+            transcoded_image: None,
+            gallery: None,
         };
 
         assert_eq!(expected, post);
     }
+
+    #[test]
+    fn malformed_post() {
+        let url = Url::from_str("https://www.reddit.com/r/aww/comments/oi6lfk/").unwrap();
+
+        let inputs = [
+            serde_json::json!(null),
+            serde_json::json!({}),
+            serde_json::json!([]),
+            serde_json::json!([{ "data": { "children": [] } }]),
+            serde_json::json!([{ "data": { "children": [{ "data": { "title": 5 } }] } }]),
+            serde_json::json!([{ "data": { "children": [{ "data": {
+                "subreddit": "aww", "title": "", "selftext": "", "over_18": false, "spoiler": false,
+                "is_gallery": true, "gallery_data": { "items": [{ "media_id": "x" }] },
+                "media_metadata": { "x": { "s": { "u": "not a url" } } },
+            } }] } }]),
+        ];
+
+        for json in inputs {
+            assert!(Api::analyze_post(url.clone(), &json).is_err(), "{json}");
+        }
+    }
+
+    #[test]
+    fn removed_post() {
+        let url = Url::from_str("https://www.reddit.com/r/aww/comments/oi6lfk/").unwrap();
+        let json =
+            serde_json::json!([{ "data": { "children": [{ "data": { "removed_by_category": "moderator" } }] } }]);
+
+        let e = Api::analyze_post(url, &json).unwrap_err();
+        assert_eq!(ScrapeError::classify(&e), ScrapeError::NotFound);
+    }
 }
@@ -0,0 +1,152 @@
+use reqwest::StatusCode;
+use thiserror::Error;
+
+/// Why a module was unable to scrape a post, in terms users understand.
+/// Modules return it through `anyhow`, either as the error itself or as context.
+#[derive(Debug, Error, Copy, Clone, PartialEq, Eq)]
+pub enum ScrapeError {
+    #[error("post not found or removed")]
+    NotFound,
+
+    #[error("post is private or quarantined")]
+    Private,
+
+    #[error("rate limited by the site")]
+    RateLimited,
+
+    #[error("site layout changed")]
+    LayoutChanged,
+
+    #[error("media too large")]
+    TooLarge,
+
+    #[error("network timeout")]
+    Timeout,
+
+    #[error("unknown error")]
+    Other,
+}
+
+impl ScrapeError {
+    fn from_status(status: StatusCode) -> Option<Self> {
+        match status {
+            StatusCode::NOT_FOUND | StatusCode::GONE => Some(Self::NotFound),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN | StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS => {
+                Some(Self::Private)
+            },
+            StatusCode::TOO_MANY_REQUESTS => Some(Self::RateLimited),
+            StatusCode::PAYLOAD_TOO_LARGE => Some(Self::TooLarge),
+            StatusCode::REQUEST_TIMEOUT | StatusCode::GATEWAY_TIMEOUT => Some(Self::Timeout),
+            _ => None,
+        }
+    }
+
+    /// Works out what went wrong from an error returned by a module.
+    /// The innermost explicit [`ScrapeError`] wins, otherwise the error is guessed from its causes.
+    pub fn classify(e: &anyhow::Error) -> Self {
+        let explicit = e
+            .chain()
+            .filter_map(|cause| cause.downcast_ref::<ScrapeError>())
+            .last()
+            .or_else(|| e.downcast_ref::<ScrapeError>());
+
+        if let Some(kind) = explicit {
+            return *kind;
+        }
+
+        for cause in e.chain() {
+            if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+                if let Some(kind) = e.status().and_then(Self::from_status) {
+                    return kind;
+                }
+
+                if e.is_timeout() {
+                    return Self::Timeout;
+                }
+            }
+
+            if cause.is::<serde_json::Error>() {
+                return Self::LayoutChanged;
+            }
+        }
+
+        Self::Other
+    }
+
+    /// The text [`super::Error::user_message`] shows for this kind of error.
+    pub fn user_message(self, locale: &str) -> &'static str {
+        match (locale.split('-').next().unwrap_or_default(), self) {
+            ("de", Self::NotFound) => "Den Post gibt es nicht (mehr), vielleicht wurde er gelöscht.",
+            ("de", Self::Private) => "Der Post ist privat oder gesperrt.",
+            ("de", Self::RateLimited) => "Die Seite nimmt gerade keine Anfragen an, versuch es später nochmal.",
+            ("de", Self::LayoutChanged) => {
+                "Die Seite hat sich verändert, Posts von dort können gerade nicht eingebettet werden."
+            },
+            ("de", Self::TooLarge) => "Die Medien des Posts sind zu groß für Discord.",
+            ("de", Self::Timeout) => "Die Seite hat zu lange nicht geantwortet, versuch es später nochmal.",
+            ("de", Self::Other) => "Der Post konnte nicht geladen werden.",
+
+            (_, Self::NotFound) => "This post does not exist (anymore), it may have been removed.",
+            (_, Self::Private) => "This post is private or quarantined.",
+            (_, Self::RateLimited) => "The site is not accepting requests right now, try again later.",
+            (_, Self::LayoutChanged) => "The site has changed, posts from it cannot be embedded right now.",
+            (_, Self::TooLarge) => "The media of this post is too large for discord.",
+            (_, Self::Timeout) => "The site took too long to respond, try again later.",
+            (_, Self::Other) => "The post could not be loaded.",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    fn status_error(status: u16) -> anyhow::Error {
+        let resp = reqwest::Response::from(http::Response::builder().status(status).body("").unwrap());
+        resp.error_for_status().unwrap_err().into()
+    }
+
+    #[test]
+    fn explicit() {
+        assert_eq!(
+            ScrapeError::classify(&ScrapeError::Private.into()),
+            ScrapeError::Private
+        );
+
+        let e = Err::<(), _>(ScrapeError::TooLarge)
+            .context("Unable to download video")
+            .unwrap_err();
+        assert_eq!(ScrapeError::classify(&e), ScrapeError::TooLarge);
+
+        // the innermost kind is the most specific one
+        let e = anyhow::Error::new(ScrapeError::NotFound).context(ScrapeError::Other);
+        assert_eq!(ScrapeError::classify(&e), ScrapeError::NotFound);
+
+        // and beats whatever the causes look like
+        let e = status_error(404).context(ScrapeError::LayoutChanged);
+        assert_eq!(ScrapeError::classify(&e), ScrapeError::LayoutChanged);
+    }
+
+    #[test]
+    fn guessed() {
+        assert_eq!(ScrapeError::classify(&status_error(410)), ScrapeError::NotFound);
+        assert_eq!(ScrapeError::classify(&status_error(403)), ScrapeError::Private);
+        assert_eq!(
+            ScrapeError::classify(&status_error(429).context("Unable to fetch post")),
+            ScrapeError::RateLimited
+        );
+        assert_eq!(ScrapeError::classify(&status_error(500)), ScrapeError::Other);
+
+        let e = serde_json::from_str::<u32>("{}").unwrap_err();
+        assert_eq!(
+            ScrapeError::classify(&anyhow::Error::new(e).context("Unable to parse post")),
+            ScrapeError::LayoutChanged
+        );
+
+        assert_eq!(
+            ScrapeError::classify(&anyhow::anyhow!("something else")),
+            ScrapeError::Other
+        );
+    }
+}
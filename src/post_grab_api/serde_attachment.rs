@@ -0,0 +1,57 @@
+//! (De)serializes attachments together with their data, which serenity skips.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serenity::builder::CreateAttachment;
+
+#[derive(Serialize, Deserialize)]
+struct Attachment {
+    filename: String,
+    description: Option<String>,
+
+    /// base64 encoded
+    data: String,
+}
+
+impl From<&CreateAttachment> for Attachment {
+    fn from(a: &CreateAttachment) -> Self {
+        Self {
+            filename: a.filename.clone(),
+            description: a.description.clone(),
+            data: STANDARD.encode(&a.data),
+        }
+    }
+}
+
+impl Attachment {
+    fn decode<E: serde::de::Error>(self) -> Result<CreateAttachment, E> {
+        let data = STANDARD.decode(self.data).map_err(E::custom)?;
+        let mut attachment = CreateAttachment::bytes(data, self.filename);
+        attachment.description = self.description;
+        Ok(attachment)
+    }
+}
+
+pub fn serialize<S: Serializer>(attachment: &CreateAttachment, s: S) -> Result<S::Ok, S::Error> {
+    Attachment::from(attachment).serialize(s)
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<CreateAttachment, D::Error> {
+    Attachment::deserialize(d)?.decode()
+}
+
+pub mod option {
+    use super::Attachment;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use serenity::builder::CreateAttachment;
+
+    pub fn serialize<S: Serializer>(attachment: &Option<CreateAttachment>, s: S) -> Result<S::Ok, S::Error> {
+        attachment.as_ref().map(Attachment::from).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<CreateAttachment>, D::Error> {
+        Option::<Attachment>::deserialize(d)?
+            .map(Attachment::decode)
+            .transpose()
+    }
+}
@@ -1,6 +1,9 @@
 #![cfg(feature = "svg")]
 
-use super::{wget, CreateResponse, EmbedOptions, Post as PostTrait, PostScraper, Url};
+use super::{
+    response_content_type, serde_attachment, tempfile_attachment, wget, ContentType, CreateResponse, EmbedOptions,
+    Post as PostTrait, PostScraper, Url,
+};
 use anyhow::Context;
 use resvg::{tiny_skia, usvg};
 use serde::{Deserialize, Serialize};
@@ -17,36 +20,41 @@ impl Api {
     }
 }
 
-#[derive(Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Post {
     src: Url,
+
+    #[serde(with = "serde_attachment")]
     attachment: CreateAttachment,
 }
 
 impl Api {
-    async fn scrape_post(url: Url) -> anyhow::Result<Post> {
-        let res = wget(url.clone()).await?;
-        let svg_str = res.text().await?;
-
-        let svg = usvg::Tree::from_str(&svg_str, &usvg::Options::default(), &usvg::fontdb::Database::default())?;
+    fn render(svg_str: &str) -> anyhow::Result<tiny_skia::Pixmap> {
+        let svg = usvg::Tree::from_str(svg_str, &usvg::Options::default(), &usvg::fontdb::Database::default())?;
 
         let size = svg.size();
 
-        let mut pix = tiny_skia::Pixmap::new(size.width() as u32, size.height() as u32).unwrap();
+        let mut pix = tiny_skia::Pixmap::new(size.width() as u32, size.height() as u32).context("svg has no area")?;
         resvg::render(&svg, usvg::Transform::identity(), &mut pix.as_mut());
 
-        let path = tempfile::Builder::new()
-            .suffix(".png")
-            .tempfile()
-            .context("Unable to create tempfile")?
-            .into_temp_path();
+        Ok(pix)
+    }
+
+    async fn scrape_post(url: Url) -> anyhow::Result<Post> {
+        let res = wget(url.clone()).await?.error_for_status()?;
+
+        match response_content_type(&res).as_deref() {
+            // some servers label svgs as generic binary or xml data, let usvg decide in that case
+            None | Some("image/svg+xml" | "application/octet-stream" | "text/xml" | "application/xml") => (),
+            Some(other) => anyhow::bail!("url does not serve an svg, but '{other}'"),
+        }
 
-        pix.save_png(&path).context("Unable to save PNG")?;
+        let pix = Self::render(&res.text().await?)?;
 
-        let file = tokio::fs::File::open(path).await.context("Unable to open tempfile")?;
-        let attachment = CreateAttachment::file(&file, "image.png")
-            .await
-            .context("Unable to read tempfile")?;
+        let attachment = tempfile_attachment("image.png", move |path| {
+            pix.save_png(path).context("Unable to save PNG")
+        })
+        .await?;
 
         Ok(Post { src: url, attachment })
     }
@@ -56,6 +64,10 @@ impl Api {
 impl PostScraper for Api {
     type Output = Post;
 
+    fn name(&self) -> &'static str {
+        "svg"
+    }
+
     fn is_suitable(&self, url: &Url) -> bool {
         url.path()
             .trim_end_matches('/')
@@ -65,6 +77,10 @@ impl PostScraper for Api {
             == Some(true)
     }
 
+    fn is_suitable_content_type(&self, content_type: &str) -> bool {
+        content_type == "image/svg+xml"
+    }
+
     fn should_embed(&self, _post: &Self::Output) -> bool {
         true
     }
@@ -89,4 +105,36 @@ impl PostTrait for Post {
             discord_comment = discord_comment,
         ))
     }
+
+    fn content_type(&self) -> ContentType {
+        ContentType::Image
+    }
+
+    fn canonical_url(&self) -> Url {
+        self.src.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn malformed_svg() {
+        let inputs = [
+            "",
+            "not xml",
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="0" height="0"/>"#,
+        ];
+
+        for svg in inputs {
+            assert!(Api::render(svg).is_err(), "{svg}");
+        }
+    }
+
+    #[test]
+    fn render_svg() {
+        let pix = Api::render(r#"<svg xmlns="http://www.w3.org/2000/svg" width="4" height="2"/>"#).unwrap();
+        assert_eq!((pix.width(), pix.height()), (4, 2));
+    }
 }
@@ -0,0 +1,169 @@
+#![cfg(feature = "transcode")]
+
+use super::{
+    serde_attachment, spawn_blocking, tempfile_attachment, wget_bytes_limited, ContentType, CreateResponse,
+    EmbedOptions, Post as PostTrait, PostScraper, Url, ATTACHMENT_MAX_SIZE,
+};
+use anyhow::Context;
+use image::ImageFormat;
+use serde::{Deserialize, Serialize};
+use serenity::{async_trait, builder::CreateAttachment, model::user::User};
+
+/// Image formats discord does not display but that can be decoded and converted to PNG.
+/// HEIC is not among them since decoding it requires libheif.
+const TRANSCODABLE_EXTENSIONS: [&str; 4] = ["tif", "tiff", "bmp", "dib"];
+const TRANSCODABLE_CONTENT_TYPES: [&str; 4] = ["image/tiff", "image/bmp", "image/x-bmp", "image/x-ms-bmp"];
+
+pub fn needs_transcoding(url: &Url) -> bool {
+    url.path()
+        .trim_end_matches('/')
+        .rsplit_once('.')
+        .map(|(_, ext)| TRANSCODABLE_EXTENSIONS.iter().any(|x| ext.eq_ignore_ascii_case(x)))
+        .unwrap_or(false)
+}
+
+/// Downloads the image at `url` and converts it to a PNG attachment named `filename`.
+pub async fn transcode_to_png(url: Url, filename: &str) -> anyhow::Result<CreateAttachment> {
+    let data = wget_bytes_limited(url, ATTACHMENT_MAX_SIZE).await?;
+    encode_png(data, filename).await
+}
+
+/// Converts the image in `data` to a PNG attachment named `filename`.
+async fn encode_png(data: Vec<u8>, filename: &str) -> anyhow::Result<CreateAttachment> {
+    let img = spawn_blocking(move || image::load_from_memory(&data))
+        .await?
+        .context("Unable to decode image")?;
+
+    tempfile_attachment(filename, move |path| {
+        img.save_with_format(path, ImageFormat::Png)
+            .context("Unable to save PNG")
+    })
+    .await
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ApiSettings {}
+
+pub struct Api;
+
+impl Api {
+    pub fn from_settings(_s: ApiSettings) -> Self {
+        Self
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Post {
+    src: Url,
+
+    #[serde(with = "serde_attachment")]
+    attachment: CreateAttachment,
+}
+
+#[async_trait]
+impl PostScraper for Api {
+    type Output = Post;
+
+    fn name(&self) -> &'static str {
+        "transcode"
+    }
+
+    fn is_suitable(&self, url: &Url) -> bool {
+        needs_transcoding(url)
+    }
+
+    fn is_suitable_content_type(&self, content_type: &str) -> bool {
+        TRANSCODABLE_CONTENT_TYPES.contains(&content_type)
+    }
+
+    fn should_embed(&self, _post: &Self::Output) -> bool {
+        true
+    }
+
+    async fn get_post(&self, url: Url) -> anyhow::Result<Self::Output> {
+        let attachment = transcode_to_png(url.clone(), "image.png").await?;
+        Ok(Post { src: url, attachment })
+    }
+}
+
+impl PostTrait for Post {
+    fn create_embed(&self, u: &User, opts: &EmbedOptions, response: CreateResponse) -> CreateResponse {
+        let discord_comment = opts
+            .comment
+            .as_ref()
+            .map(|c| format!("**Comment By {author}:**\n{comment}\n\n", author = u.name, comment = c))
+            .unwrap_or_default();
+
+        response.add_file(self.attachment.clone()).content(format!(
+            ">>> **{author}**\nSource: <{src}>\n\n{discord_comment}",
+            author = u.name,
+            src = &self.src,
+            discord_comment = discord_comment,
+        ))
+    }
+
+    fn content_type(&self) -> ContentType {
+        ContentType::Image
+    }
+
+    fn canonical_url(&self) -> Url {
+        self.src.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn transcodable_urls() {
+        let url = |s: &str| Url::parse(s).unwrap();
+
+        assert!(needs_transcoding(&url("https://example.com/scan.TIFF")));
+        assert!(needs_transcoding(&url("https://example.com/image.bmp/")));
+        assert!(!needs_transcoding(&url("https://example.com/photo.jpg")));
+        assert!(!needs_transcoding(&url("https://example.com/photo.heic")));
+    }
+
+    #[tokio::test]
+    async fn bmp_to_png() {
+        let mut bmp = Vec::new();
+        image::RgbImage::new(3, 2)
+            .write_to(&mut Cursor::new(&mut bmp), ImageFormat::Bmp)
+            .unwrap();
+
+        let attachment = encode_png(bmp, "image.png").await.unwrap();
+
+        assert_eq!(attachment.filename, "image.png");
+        assert_eq!(image::guess_format(&attachment.data).unwrap(), ImageFormat::Png);
+    }
+
+    #[tokio::test]
+    async fn size_limit() {
+        let url = crate::post_grab_api::serve(|_| crate::post_grab_api::http_response("200 OK", &[0; 100]));
+
+        assert_eq!(wget_bytes_limited(url.clone(), 100).await.unwrap().len(), 100);
+
+        let e = wget_bytes_limited(url, 50).await.unwrap_err();
+        assert_eq!(
+            crate::post_grab_api::ScrapeError::classify(&e),
+            crate::post_grab_api::ScrapeError::TooLarge
+        );
+    }
+
+    #[tokio::test]
+    async fn malformed_images() {
+        let mut bmp = Vec::new();
+        image::RgbImage::new(64, 64)
+            .write_to(&mut Cursor::new(&mut bmp), ImageFormat::Bmp)
+            .unwrap();
+
+        let header_only = bmp[..54].to_vec();
+        let truncated = bmp[..bmp.len() / 2].to_vec();
+
+        for data in [header_only, truncated, b"BM garbage".to_vec(), Vec::new()] {
+            assert!(encode_png(data, "image.png").await.is_err());
+        }
+    }
+}
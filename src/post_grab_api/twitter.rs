@@ -1,9 +1,13 @@
 #![cfg(feature = "twitter")]
 
 use super::{
-    escape_markdown, include_author_comment, limit_descr_len, CreateResponse, EmbedOptions, Post as PostTrait,
-    PostScraper,
+    collage::CollageSettings,
+    escape_markdown,
+    gallery::{Gallery, GalleryMode, Pager},
+    include_author_comment, limit_descr_len, spawn_blocking, ContentType, CreateResponse, EmbedOptions,
+    Post as PostTrait, PostScraper,
 };
+use anyhow::Context;
 use headless_chrome::LaunchOptions;
 use itertools::Itertools;
 use scraper::Html;
@@ -28,7 +32,7 @@ fn wget_rendered_html(url: &Url, chrome_executable: Option<&Path>) -> anyhow::Re
         LaunchOptions::default_builder()
             .path(chrome_executable.map(ToOwned::to_owned))
             .build()
-            .unwrap(),
+            .map_err(|e| anyhow::anyhow!("Invalid launch options: {e}"))?,
     )?;
 
     let tab = browser.new_tab()?;
@@ -39,14 +43,14 @@ fn wget_rendered_html(url: &Url, chrome_executable: Option<&Path>) -> anyhow::Re
     Ok(Html::parse_document(&content))
 }
 
-#[derive(Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct PostCommonData {
     src: Url,
     author: String,
     text: String,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum PostSpecializedData {
     Text,
     Image { img_src: Vec<Url> },
@@ -54,10 +58,13 @@ pub enum PostSpecializedData {
     VideoPreview { thumbnail_src: Url },
 }
 
-#[derive(Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Post {
     common: PostCommonData,
     specialized: PostSpecializedData,
+
+    /// the images of a multi-image tweet, prepared for display
+    gallery: Option<Gallery>,
 }
 
 fn base_embed(e: CreateEmbed, u: &User, comment: Option<&str>, post: &PostCommonData) -> CreateEmbed {
@@ -100,8 +107,12 @@ impl PostTrait for Post {
             PostSpecializedData::Image { img_src } if img_src.len() == 1 => response.embed(
                 base_embed(CreateEmbed::new(), u, opts.comment.as_deref(), &self.common).image(img_src[0].as_str()),
             ),
-            PostSpecializedData::Image { img_src } => {
-                response.content(manual_embed(u, &self.common, img_src, opts.comment.as_deref()))
+            PostSpecializedData::Image { img_src } => match &self.gallery {
+                Some(gallery) => gallery.embed(
+                    base_embed(CreateEmbed::new(), u, opts.comment.as_deref(), &self.common),
+                    response,
+                ),
+                None => response.content(manual_embed(u, &self.common, img_src, opts.comment.as_deref())),
             },
             PostSpecializedData::Video { video_src } => response.content(manual_embed(
                 u,
@@ -118,6 +129,34 @@ impl PostTrait for Post {
             ),
         }
     }
+
+    fn content_type(&self) -> ContentType {
+        match &self.specialized {
+            PostSpecializedData::Text => ContentType::Text,
+            PostSpecializedData::Image { img_src } if img_src.len() > 1 => ContentType::Gallery,
+            PostSpecializedData::Image { .. } => ContentType::Image,
+            PostSpecializedData::Video { .. } | PostSpecializedData::VideoPreview { .. } => ContentType::Video,
+        }
+    }
+
+    fn canonical_url(&self) -> Url {
+        let mut url = self.common.src.clone();
+        url.set_query(None);
+
+        if url.domain() == Some("x.com") {
+            let _ = url.set_host(Some("twitter.com"));
+        }
+
+        url
+    }
+
+    fn title(&self) -> Option<String> {
+        Some(fmt_title(&self.common))
+    }
+
+    fn pager(&self) -> Option<&Pager> {
+        self.gallery.as_ref().and_then(Gallery::pager)
+    }
 }
 
 #[derive(Serialize, Deserialize, Eq, PartialEq, Hash, Debug)]
@@ -131,6 +170,14 @@ pub enum SettingsPostType {
 pub struct ApiSettings {
     pub chrome_executable: Option<PathBuf>,
     pub embed_set: HashSet<SettingsPostType>,
+
+    /// how to display tweets with multiple images
+    #[serde(default)]
+    pub gallery: GalleryMode,
+
+    /// superseded by `gallery`, still read so that older configs keep working
+    #[serde(default, skip_serializing)]
+    pub collage: Option<CollageSettings>,
 }
 
 pub struct Api {
@@ -138,15 +185,84 @@ pub struct Api {
 }
 
 impl Api {
-    pub fn from_settings(settings: ApiSettings) -> Self {
+    pub fn from_settings(mut settings: ApiSettings) -> Self {
+        settings.gallery = std::mem::take(&mut settings.gallery).or_legacy_collage(settings.collage.take());
         Self { settings }
     }
+
+    fn analyze_post(url: Url, html: &Html) -> anyhow::Result<Post> {
+        let author = url
+            .path_segments()
+            .ok_or_else(|| anyhow::anyhow!("Url missing path"))?
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Url missing first path element"))?
+            .to_owned();
+
+        let text = {
+            let selector = scraper::Selector::parse(r#"article div[data-testid="tweetText"]"#).unwrap();
+
+            html.select(&selector)
+                .next()
+                .map(|e| e.text().filter(|&s| s != "…").collect())
+                .unwrap_or_default()
+        };
+
+        let common = PostCommonData { text, author, src: url };
+
+        let img_urls: Vec<_> = {
+            let selector = scraper::Selector::parse(r#"article img[alt]:not([alt=""])"#).unwrap();
+
+            html.select(&selector)
+                .filter_map(|e| e.attr("src"))
+                .filter(|src| src.starts_with("https://pbs.twimg.com/media"))
+                .filter_map(|s| Url::parse(s).ok())
+                .collect()
+        };
+
+        if !img_urls.is_empty() {
+            Ok(Post {
+                common,
+                specialized: PostSpecializedData::Image { img_src: img_urls },
+                gallery: None,
+            })
+        } else {
+            let selector = scraper::Selector::parse("article video").unwrap();
+
+            if let Some(video) = html.select(&selector).next() {
+                if matches!(video.attr("type"), Some("video/mp4")) {
+                    let src = video.attr("src").ok_or_else(|| anyhow::anyhow!("video without src"))?;
+
+                    Ok(Post {
+                        common,
+                        specialized: PostSpecializedData::Video { video_src: Url::parse(src)? },
+                        gallery: None,
+                    })
+                } else {
+                    let poster = video
+                        .attr("poster")
+                        .ok_or_else(|| anyhow::anyhow!("video without poster"))?;
+
+                    Ok(Post {
+                        common,
+                        specialized: PostSpecializedData::VideoPreview { thumbnail_src: Url::parse(poster)? },
+                        gallery: None,
+                    })
+                }
+            } else {
+                Ok(Post { common, specialized: PostSpecializedData::Text, gallery: None })
+            }
+        }
+    }
 }
 
 #[async_trait]
 impl PostScraper for Api {
     type Output = Post;
 
+    fn name(&self) -> &'static str {
+        "twitter"
+    }
+
     fn is_suitable(&self, url: &Url) -> bool {
         url.domain() == Some("twitter.com") || url.domain() == Some("x.com")
     }
@@ -162,64 +278,58 @@ impl PostScraper for Api {
     async fn get_post(&self, url: Url) -> anyhow::Result<Self::Output> {
         let chrome_exec = self.settings.chrome_executable.clone();
 
-        tokio::task::spawn_blocking(move || {
-            let author = url
-                .path_segments()
-                .ok_or_else(|| anyhow::anyhow!("Url missing path"))?
-                .next()
-                .ok_or_else(|| anyhow::anyhow!("Url missing first path element"))?
-                .to_owned();
-
+        spawn_blocking(move || -> anyhow::Result<Post> {
             let html = wget_rendered_html(&url, chrome_exec.as_deref())?;
+            Api::analyze_post(url, &html)
+        })
+        .await
+        .context("Scraping task failed")?
+    }
 
-            let text = {
-                let selector = scraper::Selector::parse(r#"article div[data-testid="tweetText"]"#).unwrap();
+    async fn finish_post(&self, post: &mut Self::Output) {
+        if let PostSpecializedData::Image { img_src } = &post.specialized {
+            if img_src.len() > 1 {
+                post.gallery = Gallery::prepare(&self.settings.gallery, img_src).await;
+            }
+        }
+    }
+}
 
-                html.select(&selector)
-                    .next()
-                    .map(|e| e.text().filter(|&s| s != "…").collect())
-                    .unwrap_or_default()
-            };
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            let common = PostCommonData { text, author, src: url };
+    fn analyze(html: &str) -> anyhow::Result<Post> {
+        let url = Url::parse("https://twitter.com/user/status/1").unwrap();
+        Api::analyze_post(url, &Html::parse_document(html))
+    }
 
-            let img_urls: Vec<_> = {
-                let selector = scraper::Selector::parse(r#"article img[alt]:not([alt=""])"#).unwrap();
+    #[test]
+    fn legacy_collage_setting() {
+        let settings = serde_json::from_str(r#"{ "embed_set": [], "collage": { "max_tiles": 4 } }"#).unwrap();
+        let api = Api::from_settings(settings);
+        assert!(matches!(
+            api.settings.gallery,
+            GalleryMode::Collage(CollageSettings { max_tiles: 4, .. })
+        ));
+
+        let settings = serde_json::from_str(r#"{ "embed_set": [], "gallery": { "Pager": {} } }"#).unwrap();
+        let api = Api::from_settings(settings);
+        assert!(matches!(api.settings.gallery, GalleryMode::Pager(_)));
+    }
 
-                html.select(&selector)
-                    .filter_map(|e| e.attr("src"))
-                    .filter(|src| src.starts_with("https://pbs.twimg.com/media"))
-                    .filter_map(|s| Url::parse(s).ok())
-                    .collect()
-            };
+    #[test]
+    fn text_post() {
+        let post = analyze("").unwrap();
 
-            if !img_urls.is_empty() {
-                Ok(Post { common, specialized: PostSpecializedData::Image { img_src: img_urls } })
-            } else {
-                let selector = scraper::Selector::parse("article video").unwrap();
-
-                if let Some(video) = html.select(&selector).next() {
-                    if matches!(video.attr("type"), Some("video/mp4")) {
-                        let src = video.attr("src").unwrap();
-
-                        Ok(Post {
-                            common,
-                            specialized: PostSpecializedData::Video { video_src: Url::parse(src)? },
-                        })
-                    } else {
-                        let poster = video.attr("poster").unwrap();
-
-                        Ok(Post {
-                            common,
-                            specialized: PostSpecializedData::VideoPreview { thumbnail_src: Url::parse(poster)? },
-                        })
-                    }
-                } else {
-                    Ok(Post { common, specialized: PostSpecializedData::Text })
-                }
-            }
-        })
-        .await
-        .unwrap()
+        assert_eq!(post.common.author, "user");
+        assert!(matches!(post.specialized, PostSpecializedData::Text));
+    }
+
+    #[test]
+    fn malformed_video() {
+        assert!(analyze(r#"<article><video type="video/mp4"></video></article>"#).is_err());
+        assert!(analyze(r#"<article><video type="video/mp4" src="not a url"></video></article>"#).is_err());
+        assert!(analyze("<article><video></video></article>").is_err());
     }
 }
@@ -1,25 +1,267 @@
+use super::CreateResponse;
+use anyhow::Context;
 use reqwest::IntoUrl;
-use serenity::{builder::CreateEmbed, model::user::User};
-use std::borrow::Cow;
-use url::Url;
+use serenity::{
+    builder::{CreateAttachment, CreateEmbed},
+    model::user::User,
+};
+use std::{
+    any::Any,
+    borrow::Cow,
+    fs::File,
+    io::{BufWriter, Write},
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use url::{Host, Url};
 
 const USER_AGENT: &str = concat!("github.com/Clueliss/embedbot-rs embedbot/", clap::crate_version!());
 const EMBED_CONTENT_MAX_LEN: usize = 2048;
 
 pub const EMBED_TITLE_MAX_LEN: usize = 256;
+pub const EMBED_FIELD_MAX_LEN: usize = 1024;
 
+/// The largest attachment discord accepts from bots
+pub const ATTACHMENT_MAX_SIZE: usize = 25 * 1024 * 1024;
+
+tokio::task_local! {
+    /// Whatever limits the job that is currently running, e.g. a worker of the job queue
+    pub static JOB_GUARD: Arc<dyn Any + Send + Sync>;
+}
+
+/// Like [`tokio::task::spawn_blocking`], but holds on to the [`JOB_GUARD`] of the calling job until `f` returns.
+/// Blocking work cannot be cancelled, so it has to count against the limits of its job even after that timed out.
+pub fn spawn_blocking<F, R>(f: F) -> tokio::task::JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let guard = JOB_GUARD.try_with(Arc::clone).ok();
+
+    tokio::task::spawn_blocking(move || {
+        let _guard = guard;
+        f()
+    })
+}
+
+/// A file name for `key` that stays the same across builds, unlike hashes of the std hasher.
+pub fn stable_file_stem(key: &str) -> String {
+    // FNV-1a
+    let hash = key.bytes().fold(0xcbf29ce484222325_u64, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    });
+
+    format!("{hash:016x}")
+}
+
+/// Replaces the file at `path` with what `write` writes, going through a temporary file next to it
+/// so that the file is never seen half written.
+pub fn write_atomically<F>(path: &Path, write: F) -> anyhow::Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> anyhow::Result<()>,
+{
+    static NEXT_TMP: AtomicU64 = AtomicU64::new(0);
+
+    // unique, so that concurrent writers of the same file do not write into each other's temporary file
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(format!(".{}.tmp", NEXT_TMP.fetch_add(1, Ordering::Relaxed)));
+    let tmp_path = Path::new(&tmp_path);
+
+    let res = File::create(tmp_path)
+        .with_context(|| format!("Unable to create {}", tmp_path.display()))
+        .and_then(|f| {
+            let mut f = BufWriter::new(f);
+            write(&mut f)?;
+            f.flush()
+                .with_context(|| format!("Unable to write {}", tmp_path.display()))
+        })
+        .and_then(|()| {
+            std::fs::rename(tmp_path, path).with_context(|| format!("Unable to replace {}", path.display()))
+        });
+
+    if res.is_err() {
+        let _ = std::fs::remove_file(tmp_path);
+    }
+
+    res
+}
+
+pub(super) fn get_request<U: IntoUrl>(url: U) -> reqwest::RequestBuilder {
+    reqwest::Client::new().get(url).header("User-Agent", USER_AGENT)
+}
+
+/// Fetches `url`, going through the http cache if it is enabled.
 pub async fn wget<U: IntoUrl>(url: U) -> anyhow::Result<reqwest::Response> {
-    let client = reqwest::Client::new();
-    client
-        .get(url)
+    match super::http_cache::get() {
+        Some(cache) => cache.fetch(url.into_url()?).await,
+        None => get_request(url).send().await.map_err(Into::into),
+    }
+}
+
+/// Downloads the body of `url`, giving up once it is larger than `max_size` bytes.
+#[cfg(feature = "transcode")]
+pub async fn wget_bytes_limited<U: IntoUrl>(url: U, max_size: usize) -> anyhow::Result<Vec<u8>> {
+    let mut resp = wget(url).await?.error_for_status()?;
+
+    if resp.content_length().is_some_and(|len| len > max_size as u64) {
+        anyhow::bail!(super::ScrapeError::TooLarge);
+    }
+
+    let mut data = Vec::new();
+    while let Some(chunk) = resp.chunk().await? {
+        if data.len() + chunk.len() > max_size {
+            anyhow::bail!(super::ScrapeError::TooLarge);
+        }
+
+        data.extend_from_slice(&chunk);
+    }
+
+    Ok(data)
+}
+
+pub async fn wget_json<U: IntoUrl>(url: U) -> anyhow::Result<serde_json::Value> {
+    wget(url).await?.error_for_status()?.json().await.map_err(Into::into)
+}
+
+/// Extracts the bare mime type (e.g. `image/svg+xml`) from a `Content-Type` header.
+pub fn response_content_type(resp: &reqwest::Response) -> Option<String> {
+    resp.headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .map(|v| v.trim().to_ascii_lowercase())
+        .filter(|v| !v.is_empty())
+}
+
+/// Whether `ip` is an address on the public internet, as opposed to loopback, private networks and the like.
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                // carrier-grade nat
+                || (a == 100 && (64..128).contains(&b))
+                || a == 0
+                || a >= 240)
+        },
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public_ip(IpAddr::V4(ip));
+            }
+
+            let first = ip.segments()[0];
+
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_multicast()
+                // unique local
+                || (first & 0xfe00) == 0xfc00
+                // link local
+                || (first & 0xffc0) == 0xfe80)
+        },
+    }
+}
+
+/// Resolves the host of `url`, refusing hosts that are not on the public internet.
+async fn public_addrs(url: &Url) -> anyhow::Result<Vec<SocketAddr>> {
+    let port = url.port_or_known_default().unwrap_or(80);
+
+    let addrs: Vec<_> = match url.host() {
+        Some(Host::Ipv4(ip)) => vec![SocketAddr::new(ip.into(), port)],
+        Some(Host::Ipv6(ip)) => vec![SocketAddr::new(ip.into(), port)],
+        Some(Host::Domain(domain)) => tokio::net::lookup_host((domain, port)).await?.collect(),
+        None => anyhow::bail!("url has no host"),
+    };
+
+    if addrs.is_empty() || !addrs.iter().all(|addr| is_public_ip(addr.ip())) {
+        anyhow::bail!("{} does not resolve to a public address", url);
+    }
+
+    Ok(addrs)
+}
+
+/// Asks the server for the content type of `url` without downloading the body.
+/// Tries a `HEAD` request first and falls back to a single byte ranged `GET`
+/// for servers that do not implement `HEAD` properly.
+/// Only public hosts are asked, and redirects are not followed since they could lead anywhere.
+pub async fn probe_content_type<U: IntoUrl>(url: U) -> anyhow::Result<Option<String>> {
+    let url = url.into_url()?;
+    let addrs = public_addrs(&url).await?;
+
+    let mut client = reqwest::Client::builder().redirect(reqwest::redirect::Policy::none());
+
+    // connect to the addresses that were checked, not whatever the host resolves to next
+    if let Some(Host::Domain(domain)) = url.host() {
+        client = client.resolve_to_addrs(domain, &addrs);
+    }
+
+    let client = client.build()?;
+
+    let head = client
+        .head(url.clone())
         .header("User-Agent", USER_AGENT)
         .send()
         .await
-        .map_err(Into::into)
+        .ok()
+        .filter(|resp| resp.status().is_success());
+
+    if let Some(content_type) = head.as_ref().and_then(response_content_type) {
+        return Ok(Some(content_type));
+    }
+
+    let resp = client
+        .get(url)
+        .header("User-Agent", USER_AGENT)
+        .header(reqwest::header::RANGE, "bytes=0-0")
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(response_content_type(&resp))
 }
 
-pub async fn wget_json<U: IntoUrl>(url: U) -> anyhow::Result<serde_json::Value> {
-    wget(url).await?.json().await.map_err(Into::into)
+/// Creates an attachment named `filename` from a file written by `write` into a fresh tempfile.
+#[cfg(any(feature = "svg", feature = "transcode", feature = "collage", feature = "blur"))]
+pub async fn tempfile_attachment<F>(filename: &str, write: F) -> anyhow::Result<CreateAttachment>
+where
+    F: FnOnce(&Path) -> anyhow::Result<()> + Send + 'static,
+{
+    let suffix = Path::new(filename)
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+
+    let path = tempfile::Builder::new()
+        .suffix(&suffix)
+        .tempfile()
+        .context("Unable to create tempfile")?
+        .into_temp_path();
+
+    let path = spawn_blocking(move || write(&path).map(|()| path))
+        .await
+        .context("Encoding task failed")??;
+
+    let file = tokio::fs::File::open(&path).await.context("Unable to open tempfile")?;
+    let attachment = CreateAttachment::file(&file, filename)
+        .await
+        .context("Unable to read tempfile")?;
+
+    if attachment.data.len() > ATTACHMENT_MAX_SIZE {
+        anyhow::bail!(super::ScrapeError::TooLarge);
+    }
+
+    Ok(attachment)
 }
 
 pub fn url_path_ends_with(haystack: &Url, needle: &str) -> bool {
@@ -58,11 +300,32 @@ pub fn escape_markdown(title: &str) -> String {
     MarkdownEscape::new(title.as_bytes()).to_string()
 }
 
+/// Undoes [`escape_markdown`] and bold markers.
+pub fn strip_markdown(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut chars = text.replace("**", "").chars().collect::<Vec<_>>().into_iter();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => plain.extend(chars.next()),
+            c => plain.push(c),
+        }
+    }
+
+    plain
+}
+
 pub fn limit_len(text: &str, limit: usize) -> Cow<str> {
     const SHORTENED_MARKER: &str = " [...]";
 
     if text.len() > limit {
-        format!("{}{}", &text[..(limit - SHORTENED_MARKER.len())], SHORTENED_MARKER).into()
+        let mut end = limit - SHORTENED_MARKER.len();
+
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        format!("{}{}", &text[..end], SHORTENED_MARKER).into()
     } else {
         text.into()
     }
@@ -76,3 +339,115 @@ pub fn include_author_comment(e: CreateEmbed, u: &User, comment: &str) -> Create
     let title = format!("Comment by {author}", author = u.name);
     e.field(title, comment, false)
 }
+
+/// Shows `image` as the image of `e`, if there is one.
+pub fn embed_with_attachment(
+    response: CreateResponse,
+    e: CreateEmbed,
+    image: Option<&CreateAttachment>,
+) -> CreateResponse {
+    match image {
+        Some(image) => response.embed(e.attachment(&image.filename)).add_file(image.clone()),
+        None => response.embed(e),
+    }
+}
+
+/// Serves http on a local port, answering every request with the raw response `respond` builds from it.
+#[cfg(test)]
+pub fn serve(respond: impl Fn(&str) -> Vec<u8> + Send + 'static) -> Url {
+    use std::io::{Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+
+    std::thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                match stream.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => request.extend_from_slice(&buf[..n]),
+                }
+            }
+
+            let _ = stream.write_all(&respond(&String::from_utf8_lossy(&request)));
+        }
+    });
+
+    url
+}
+
+/// A raw http response, `head` being the status followed by any extra header lines.
+#[cfg(test)]
+pub fn http_response(head: &str, body: &[u8]) -> Vec<u8> {
+    let mut response = format!(
+        "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        head,
+        body.len()
+    )
+    .into_bytes();
+    response.extend_from_slice(body);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn public_ips() {
+        let public = ["1.1.1.1", "140.82.121.4", "2606:4700:4700::1111"];
+        let internal = [
+            "127.0.0.1",
+            "10.0.0.1",
+            "172.16.5.4",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ];
+
+        for ip in public {
+            assert!(is_public_ip(ip.parse().unwrap()), "{ip}");
+        }
+
+        for ip in internal {
+            assert!(!is_public_ip(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[test]
+    fn image_extensions() {
+        let url = |s: &str| Url::parse(s).unwrap();
+
+        assert!(url_path_ends_with_image_extension(&url("https://i.imgur.com/a.jpeg")));
+        assert!(url_path_ends_with_image_extension(&url(
+            "https://example.com/scan.tiff/"
+        )));
+        assert!(url_path_ends_with_image_extension(&url(
+            "https://example.com/photo.heic"
+        )));
+        assert!(!url_path_ends_with_image_extension(&url(
+            "https://example.com/page.html"
+        )));
+    }
+
+    #[tokio::test]
+    async fn probe_internal_hosts() {
+        for url in [
+            "http://127.0.0.1:8080/",
+            "http://[::1]/",
+            "http://localhost/",
+            "http://169.254.169.254/",
+        ] {
+            assert!(probe_content_type(url).await.is_err(), "{url}");
+        }
+    }
+}